@@ -0,0 +1,505 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Which cells around a cell its next state depends on.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Neighborhood {
+    /// Every cell within `radius` steps along both axes, i.e. the 8 surrounding cells for 1.
+    Moore(usize),
+    /// Every cell within a Manhattan distance of `radius`, i.e. the 4 adjacent cells for 1.
+    VonNeumann(usize),
+    /// Every cell within `radius` steps on the same row, for one-dimensional automata.
+    Row(usize),
+}
+
+impl Neighborhood {
+    /// The offsets of the neighbors in reading order, without the cell itself.
+    fn offsets(self) -> Vec<(isize, isize)> {
+        let radius = match self {
+            Neighborhood::Moore(radius)
+            | Neighborhood::VonNeumann(radius)
+            | Neighborhood::Row(radius) => radius as isize,
+        };
+
+        (-radius..=radius)
+            .flat_map(|dy| (-radius..=radius).map(move |dx| (dx, dy)))
+            .filter(|&offset| offset != (0, 0))
+            .filter(|&(dx, dy)| match self {
+                Neighborhood::Moore(_) => true,
+                Neighborhood::VonNeumann(_) => dx.abs() + dy.abs() <= radius,
+                Neighborhood::Row(_) => dy == 0,
+            })
+            .collect()
+    }
+}
+
+/// What the neighbors beyond the edges of the grid are.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Edge<C> {
+    /// Every cell outside of the grid is in this state.
+    Dead(C),
+    /// The grid wraps around, like a torus.
+    Wrap,
+    /// The grid is mirrored at its edges, so the first cell outside is the edge cell itself.
+    Mirror,
+}
+
+/// The remainder of `value / modulus`, which unlike `%` is never negative.
+fn modulo(value: isize, modulus: isize) -> isize {
+    (value % modulus + modulus) % modulus
+}
+
+/// Maps a coordinate outside of `0..size` according to the edge, or `None` for dead cells.
+fn resolve<C>(edge: &Edge<C>, value: isize, size: usize) -> Option<usize> {
+    let size = size as isize;
+
+    if 0 <= value && value < size {
+        return Some(value as usize);
+    }
+
+    match edge {
+        Edge::Dead(_) => None,
+        Edge::Wrap => Some(modulo(value, size) as usize),
+        Edge::Mirror => {
+            let value = modulo(value, 2 * size);
+            Some(if value < size { value } else { 2 * size - 1 - value } as usize)
+        }
+    }
+}
+
+/// A detected cycle: the state at generation `start + length` is the same as at `start`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Cycle {
+    pub start: usize,
+    pub length: usize,
+}
+
+/// A two-dimensional cellular automaton, where the next state of every cell is given by a rule
+/// applied to its current state and the states of its neighbors, in reading order.
+///
+/// Generations are computed into a second buffer, which is then swapped with the current one.
+pub struct Automaton<C, R> {
+    width: usize,
+    height: usize,
+    cells: Vec<C>,
+    next_cells: Vec<C>,
+    neighbors: Vec<C>,
+    offsets: Vec<(isize, isize)>,
+    edge: Edge<C>,
+    rule: R,
+    generation: usize,
+}
+
+impl<C, R> Automaton<C, R>
+where
+    C: Copy + Default,
+    R: Fn(C, &[C]) -> C,
+{
+    /// An automaton with the cells given in reading order, using a Moore neighborhood with a
+    /// radius of 1 and a dead border of default cells.
+    pub fn new(width: usize, height: usize, cells: Vec<C>, rule: R) -> Automaton<C, R> {
+        assert_eq!(cells.len(), width * height, "Invalid number of cells");
+
+        Automaton {
+            width,
+            height,
+            next_cells: cells.clone(),
+            cells,
+            neighbors: Vec::new(),
+            offsets: Neighborhood::Moore(1).offsets(),
+            edge: Edge::Dead(C::default()),
+            rule,
+            generation: 0,
+        }
+    }
+
+    pub fn with_neighborhood(self, neighborhood: Neighborhood) -> Automaton<C, R> {
+        Automaton { offsets: neighborhood.offsets(), ..self }
+    }
+
+    pub fn with_edge(self, edge: Edge<C>) -> Automaton<C, R> {
+        Automaton { edge, ..self }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The number of generations so far.
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+
+    /// The cells, in reading order.
+    pub fn cells(&self) -> &[C] {
+        &self.cells
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> C {
+        self.cells[y * self.width + x]
+    }
+
+    /// Adds rows and columns of `fill` cells around the grid.
+    pub fn expand(&mut self, left: usize, right: usize, top: usize, bottom: usize, fill: C) {
+        let width = left + self.width + right;
+        let mut cells = vec![fill; width * top];
+
+        for y in 0..self.height {
+            cells.extend(std::iter::repeat(fill).take(left));
+            cells.extend_from_slice(&self.cells[y * self.width..(y + 1) * self.width]);
+            cells.extend(std::iter::repeat(fill).take(right));
+        }
+
+        cells.extend(std::iter::repeat(fill).take(width * bottom));
+
+        self.width = width;
+        self.height += top + bottom;
+        self.next_cells = cells.clone();
+        self.cells = cells;
+    }
+
+    /// Removes rows and columns of cells from the edges of the grid.
+    pub fn crop(&mut self, left: usize, right: usize, top: usize, bottom: usize) {
+        assert!(left + right <= self.width && top + bottom <= self.height, "Cropped too much");
+
+        let width = self.width - left - right;
+        let height = self.height - top - bottom;
+        let mut cells = Vec::with_capacity(width * height);
+
+        for y in top..top + height {
+            let start = y * self.width + left;
+            cells.extend_from_slice(&self.cells[start..start + width]);
+        }
+
+        self.width = width;
+        self.height = height;
+        self.next_cells = cells.clone();
+        self.cells = cells;
+    }
+
+    /// Computes the next generation.
+    ///
+    /// The neighbors of cells that are far enough from the edges are read straight from their
+    /// indices, and only the cells near the edges go through the edge behavior.
+    pub fn step(&mut self) {
+        let Automaton { width, height, .. } = *self;
+
+        let reach_x = self.offsets.iter().map(|&(dx, _)| dx.abs()).max().unwrap_or(0) as usize;
+        let reach_y = self.offsets.iter().map(|&(_, dy)| dy.abs()).max().unwrap_or(0) as usize;
+        let steps =
+            self.offsets.iter().map(|&(dx, dy)| dy * width as isize + dx).collect::<Vec<_>>();
+
+        for y in 0..height {
+            for x in 0..width {
+                let index = y * width + x;
+                self.neighbors.clear();
+
+                if reach_x <= x && x + reach_x < width && reach_y <= y && y + reach_y < height {
+                    for &step in &steps {
+                        self.neighbors.push(self.cells[(index as isize + step) as usize]);
+                    }
+                } else {
+                    for &(dx, dy) in &self.offsets {
+                        let x = resolve(&self.edge, x as isize + dx, width);
+                        let y = resolve(&self.edge, y as isize + dy, height);
+
+                        self.neighbors.push(match (x, y, &self.edge) {
+                            (Some(x), Some(y), _) => self.cells[y * width + x],
+                            (_, _, Edge::Dead(dead)) => *dead,
+                            _ => unreachable!(),
+                        });
+                    }
+                }
+
+                self.next_cells[index] = (self.rule)(self.cells[index], &self.neighbors);
+            }
+        }
+
+        std::mem::swap(&mut self.cells, &mut self.next_cells);
+        self.generation += 1;
+    }
+}
+
+impl<C, R> Automaton<C, R>
+where
+    C: Copy + Default + Eq + Hash,
+    R: Fn(C, &[C]) -> C,
+{
+    /// Steps until the cells are in a state they were already in since the call, for at most
+    /// `limit` generations. States are compared in full, so a cycle that is found is certain.
+    pub fn find_cycle(&mut self, limit: usize) -> Option<Cycle> {
+        let target = self.generation + limit;
+        let mut seen = HashMap::new();
+
+        loop {
+            if let Some(&start) = seen.get(&self.cells) {
+                return Some(Cycle { start, length: self.generation - start });
+            }
+
+            if self.generation == target {
+                return None;
+            }
+
+            seen.insert(self.cells.clone(), self.generation);
+            self.step();
+        }
+    }
+
+    /// Advances by a number of generations. Once a state repeats, the remaining full cycles are
+    /// skipped, and the cycle that was found is returned.
+    pub fn advance(&mut self, generations: usize) -> Option<Cycle> {
+        let target = self.generation + generations;
+        let cycle = self.find_cycle(generations)?;
+
+        for _ in 0..(target - self.generation) % cycle.length {
+            self.step();
+        }
+
+        self.generation = target;
+        Some(cycle)
+    }
+}
+
+/// A one-dimensional automaton of two-state cells packed into bits, on a row that is unbounded in
+/// both directions and dead everywhere outside of the live cells.
+///
+/// The next state of every cell is looked up in a table, indexed by the bits of its neighborhood
+/// of `2 * radius + 1` cells, from the leftmost cell in the highest bit to the rightmost cell in
+/// the lowest bit. Generations are computed by sliding that window over the bits.
+pub struct PackedRow {
+    /// The position of the leftmost live cell, or 0 if no cell is live.
+    offset: isize,
+    /// Bit `i` is set if the cell at `offset + i` is live. The last word is never zero.
+    bits: Vec<u64>,
+    next_bits: Vec<u64>,
+    radius: usize,
+    table: Vec<bool>,
+    generation: usize,
+}
+
+impl PackedRow {
+    /// A row with the given live cells, which must be in increasing order.
+    pub fn new(
+        live: impl IntoIterator<Item = isize>,
+        radius: usize,
+        table: Vec<bool>,
+    ) -> PackedRow {
+        assert_eq!(table.len(), 1 << (2 * radius + 1), "Invalid rule table");
+        assert!(!table[0], "Dead neighborhoods must stay dead");
+
+        let mut bits = Vec::new();
+        let mut offset = None;
+
+        for position in live {
+            let offset = *offset.get_or_insert(position);
+            assert!(position >= offset, "Live cells must be in increasing order");
+            set_bit(&mut bits, (position - offset) as usize);
+        }
+
+        PackedRow {
+            offset: offset.unwrap_or(0),
+            bits,
+            next_bits: Vec::new(),
+            radius,
+            table,
+            generation: 0,
+        }
+    }
+
+    /// The position of the leftmost live cell, or 0 if no cell is live.
+    pub fn offset(&self) -> isize {
+        self.offset
+    }
+
+    /// The live cells packed into words, starting with the leftmost live cell in the lowest bit.
+    pub fn words(&self) -> &[u64] {
+        &self.bits
+    }
+
+    /// The number of bits up to and including the rightmost live cell.
+    pub fn len(&self) -> usize {
+        self.bits.last().map_or(0, |word| self.bits.len() * 64 - word.leading_zeros() as usize)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits.is_empty()
+    }
+
+    /// The number of generations so far.
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+
+    fn get(&self, bit: usize) -> bool {
+        self.bits.get(bit / 64).map_or(false, |word| word >> (bit % 64) & 1 == 1)
+    }
+
+    /// Computes the next generation, from `radius` cells left of the leftmost live cell to
+    /// `radius` cells right of the rightmost one, since nothing further away can come alive.
+    pub fn step(&mut self) {
+        let radius = self.radius;
+        let mask = (1 << (2 * radius + 1)) - 1;
+        let mut window = 0;
+        let mut first = None;
+
+        self.next_bits.clear();
+
+        // After reading `bit`, the window is centered on the cell at `offset + bit - radius`
+        for bit in 0..self.len() + 2 * radius {
+            window = (window << 1 | self.get(bit) as usize) & mask;
+
+            if self.table[window] {
+                let first = *first.get_or_insert(bit);
+                set_bit(&mut self.next_bits, bit - first);
+            }
+        }
+
+        self.offset = first.map_or(0, |first| self.offset + first as isize - radius as isize);
+        std::mem::swap(&mut self.bits, &mut self.next_bits);
+        self.generation += 1;
+    }
+}
+
+fn set_bit(bits: &mut Vec<u64>, bit: usize) {
+    if bits.len() <= bit / 64 {
+        bits.resize(bit / 64 + 1, 0);
+    }
+    bits[bit / 64] |= 1 << (bit % 64);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn life(cell: bool, neighbors: &[bool]) -> bool {
+        match (cell, neighbors.iter().filter(|&&alive| alive).count()) {
+            (true, 2) | (_, 3) => true,
+            _ => false,
+        }
+    }
+
+    fn parse(input: &str) -> (usize, usize, Vec<bool>) {
+        let lines = input.lines().collect::<Vec<_>>();
+        let cells = lines.iter().flat_map(|line| line.chars().map(|c| c == '#')).collect();
+        (lines[0].len(), lines.len(), cells)
+    }
+
+    #[test]
+    fn neighborhoods_work() {
+        assert_eq!(Neighborhood::Moore(1).offsets().len(), 8);
+        assert_eq!(Neighborhood::Moore(2).offsets().len(), 24);
+        assert_eq!(Neighborhood::VonNeumann(1).offsets(), vec![(0, -1), (-1, 0), (1, 0), (0, 1)]);
+        assert_eq!(Neighborhood::VonNeumann(2).offsets().len(), 12);
+        assert_eq!(Neighborhood::Row(2).offsets(), vec![(-2, 0), (-1, 0), (1, 0), (2, 0)]);
+    }
+
+    #[test]
+    fn edges_work() {
+        assert_eq!(resolve(&Edge::Dead(false), -1, 5), None);
+        assert_eq!(resolve::<bool>(&Edge::Wrap, -1, 5), Some(4));
+        assert_eq!(resolve::<bool>(&Edge::Wrap, 5, 5), Some(0));
+        assert_eq!(resolve::<bool>(&Edge::Mirror, -1, 5), Some(0));
+        assert_eq!(resolve::<bool>(&Edge::Mirror, -2, 5), Some(1));
+        assert_eq!(resolve::<bool>(&Edge::Mirror, 6, 5), Some(3));
+    }
+
+    #[test]
+    fn blinkers_blink() {
+        let (width, height, cells) = parse(".....\n..#..\n..#..\n..#..\n.....");
+        let mut automaton = Automaton::new(width, height, cells.clone(), life);
+
+        automaton.step();
+
+        assert_eq!(automaton.cells(), &parse(".....\n.....\n.###.\n.....\n.....").2[..]);
+        assert_eq!(automaton.advance(1001), Some(Cycle { start: 1, length: 2 }));
+        assert_eq!(automaton.generation(), 1002);
+        assert_eq!(automaton.cells(), &cells[..]);
+    }
+
+    #[test]
+    fn gliders_wrap_around() {
+        let (width, height, cells) = parse(".#....\n..#...\n###...\n......\n......\n......");
+        let mut automaton =
+            Automaton::new(width, height, cells.clone(), life).with_edge(Edge::Wrap);
+
+        // A glider moves one cell diagonally every 4 generations
+        assert_eq!(automaton.advance(100), Some(Cycle { start: 0, length: 24 }));
+        assert_eq!(
+            automaton.cells(),
+            &parse("......\n..#...\n...#..\n.###..\n......\n......").2[..]
+        );
+
+        let mut automaton = Automaton::new(width, height, cells, life);
+        automaton.advance(100);
+
+        // Without wrapping, it turns into a block in the corner
+        assert_eq!(
+            automaton.cells(),
+            &parse("......\n......\n......\n......\n....##\n....##").2[..]
+        );
+    }
+
+    #[test]
+    fn expanding_works() {
+        let mut automaton = Automaton::new(2, 1, vec![true, true], life);
+        automaton.expand(1, 2, 1, 0, false);
+
+        assert_eq!((automaton.width(), automaton.height()), (5, 2));
+        assert!(automaton.get(1, 1));
+        assert!(!automaton.get(3, 1));
+
+        automaton.crop(1, 1, 1, 0);
+
+        assert_eq!((automaton.width(), automaton.height()), (3, 1));
+        assert_eq!(automaton.cells(), &[true, true, false][..]);
+
+        automaton.crop(0, 3, 0, 0);
+        automaton.expand(1, 1, 0, 0, true);
+
+        assert_eq!(automaton.cells(), &[true, true][..]);
+    }
+
+    #[test]
+    fn packed_rows_work() {
+        // Rule 90, where a cell is live if exactly one of its neighbors was
+        let table = (0..8).map(|window| (window >> 2 ^ window) & 1 == 1).collect();
+        let mut row = PackedRow::new(vec![0], 1, table);
+        let cells = |row: &PackedRow| {
+            (0..row.len()).map(|bit| if row.get(bit) { '#' } else { '.' }).collect::<String>()
+        };
+
+        row.step();
+        assert_eq!((row.offset(), cells(&row).as_str()), (-1, "#.#"));
+
+        row.step();
+        assert_eq!((row.offset(), cells(&row).as_str()), (-2, "#...#"));
+
+        // Far enough out to span several words
+        for _ in 2..100 {
+            row.step();
+        }
+        assert_eq!(row.generation(), 100);
+        assert_eq!((row.offset(), row.len(), row.words().len()), (-100, 201, 4));
+
+        // A cell that dies without neighbors leaves an empty row behind
+        let mut row = PackedRow::new(vec![5], 1, vec![false; 8]);
+        row.step();
+        assert!(row.is_empty());
+        assert_eq!(row.offset(), 0);
+    }
+
+    #[test]
+    fn mirrored_edges_work() {
+        // With the border mirrored, a live row along the top edge sees itself above, so it is
+        // overcrowded, while every cell below it has three live neighbors
+        let (width, height, cells) = parse("###\n...\n...");
+        let mut automaton = Automaton::new(width, height, cells, life).with_edge(Edge::Mirror);
+        automaton.step();
+
+        assert_eq!(automaton.cells(), &parse("...\n###\n...").2[..]);
+    }
+}
@@ -1,14 +1,21 @@
-use chrono::{Duration, NaiveDateTime, Timelike};
+use chrono::{Duration, NaiveDate, NaiveDateTime, Timelike};
+use core::fmt;
 use itertools::Itertools;
 use lazy_static::lazy_static;
-use std::collections::HashMap;
+use regex::Regex;
+use std::collections::BTreeMap;
 use std::iter::Iterator;
+use std::ops::Range;
 
 lazy_static! {
     static ref ONE_MINUTE: Duration = Duration::minutes(1);
+    static ref ENTRY_PATTERN: Regex = Regex::new(r"^\[\s*(\S+)\s+(\S+?)\s*\]\s*(.*?)$").unwrap();
+    static ref GUARD_PATTERN: Regex = Regex::new(r"^Guard\s+#(\d+)\s+begins\s+shift$").unwrap();
+    static ref SLEEP_PATTERN: Regex = Regex::new(r"^falls\s+asleep$").unwrap();
+    static ref WAKE_PATTERN: Regex = Regex::new(r"^wakes\s+up$").unwrap();
 }
 
-type ID = u32;
+pub type ID = u32;
 
 #[derive(Debug)]
 enum Action {
@@ -27,96 +34,416 @@ impl Action {
     }
 }
 
-#[derive(Debug)]
-enum State {
-    Vacant,
-    Working { date: NaiveDateTime, id: ID },
-    Sleeping { date: NaiveDateTime, id: ID },
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum LogErrorKind {
+    InvalidEntry(String),
+    InvalidTimestamp(String),
+    UnknownEvent(String),
+    /// A guard fell asleep or woke up before any shift began.
+    NoGuardOnDuty,
+    AlreadyAsleep,
+    AlreadyAwake,
 }
 
-fn actions_iter<'a>(input: &'a str) -> impl Iterator<Item = Action> + 'a {
-    input.trim().lines().map(|line| {
-        let date = NaiveDateTime::parse_from_str(&line[1..17], "%Y-%m-%d %H:%M").unwrap();
+/// A line of the log that could not be used, with its (1-based) line number.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LogError {
+    pub line: usize,
+    pub kind: LogErrorKind,
+}
 
-        match &line[19..24] {
-            "Guard" => {
-                let slice = &line[26..];
-                let end_index = slice.find(char::is_whitespace).unwrap();
-                let id = slice[..end_index].parse().unwrap();
-                Action::BeginShift { date, id }
+impl fmt::Display for LogErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LogErrorKind::InvalidEntry(entry) => write!(f, "invalid entry `{}`", entry),
+            LogErrorKind::InvalidTimestamp(timestamp) => {
+                write!(f, "invalid timestamp `{}`", timestamp)
             }
-            "wakes" => Action::WakeUp { date },
-            "falls" => Action::FallAsleep { date },
-            value => {
-                panic!("Unexpected value: {}", value);
+            LogErrorKind::UnknownEvent(event) => write!(f, "unknown event `{}`", event),
+            LogErrorKind::NoGuardOnDuty => write!(f, "no guard is on duty"),
+            LogErrorKind::AlreadyAsleep => write!(f, "the guard is already asleep"),
+            LogErrorKind::AlreadyAwake => write!(f, "the guard is already awake"),
+        }
+    }
+}
+
+impl fmt::Display for LogError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.kind)
+    }
+}
+
+/// Something odd about the log that could still be made sense of.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Warning {
+    /// A guard that fell asleep on a line never woke up, so the sleep ends with the shift.
+    DanglingSleep { line: usize, id: ID },
+    /// A shift began on a line before the shift of the previous guard had ended.
+    OverlappingShifts { line: usize, previous: ID, next: ID },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Warning::DanglingSleep { line, id } => {
+                write!(f, "line {}: guard #{} never wakes up", line, id)
             }
+            Warning::OverlappingShifts { line, previous, next } => write!(
+                f,
+                "line {}: guard #{} begins before the shift of guard #{} ends",
+                line, next, previous
+            ),
         }
-    })
+    }
+}
+
+/// A parsed log, with everything that was off about it.
+#[derive(Debug, Clone)]
+pub struct ParseReport {
+    pub log: GuardLog,
+    pub skipped: Vec<LogError>,
+    pub warnings: Vec<Warning>,
+}
+
+/// A guard's shift, which covers the midnight hour of its date.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Shift {
+    pub id: ID,
+    pub start: NaiveDateTime,
+    /// The times the guard was asleep, where every end is the minute the guard woke up.
+    pub sleeps: Vec<Range<NaiveDateTime>>,
+}
+
+impl Shift {
+    /// The date of the midnight hour the shift covers, which is the next day for guards that
+    /// begin their shift before midnight.
+    pub fn date(&self) -> NaiveDate {
+        if self.start.hour() >= 12 {
+            self.start.date().succ()
+        } else {
+            self.start.date()
+        }
+    }
+
+    /// The end of the midnight hour, when the shift is over.
+    pub fn end(&self) -> NaiveDateTime {
+        self.date().and_hms(1, 0, 0)
+    }
+
+    /// The times the guard was asleep during the midnight hour, which is all that counts.
+    pub fn midnight_sleeps<'a>(&'a self) -> impl Iterator<Item = Range<NaiveDateTime>> + 'a {
+        let (midnight, end) = (self.date().and_hms(0, 0, 0), self.end());
+
+        self.sleeps
+            .iter()
+            .map(move |sleep| sleep.start.max(midnight)..sleep.end.min(end))
+            .filter(|sleep| sleep.start < sleep.end)
+    }
+
+    /// The minutes the guard was asleep during the midnight hour.
+    pub fn minutes_asleep(&self) -> u32 {
+        self.midnight_sleeps().map(|sleep| (sleep.end - sleep.start).num_minutes() as u32).sum()
+    }
+
+    pub fn is_asleep(&self, time: NaiveDateTime) -> bool {
+        self.sleeps.iter().any(|sleep| sleep.start <= time && time < sleep.end)
+    }
+
+    fn minutes_asleep_iter<'a>(&'a self) -> impl Iterator<Item = NaiveDateTime> + 'a {
+        self.midnight_sleeps().flat_map(|sleep| {
+            let minutes = (sleep.end - sleep.start).num_minutes();
+            (0..minutes).map(move |minute| sleep.start + *ONE_MINUTE * minute as i32)
+        })
+    }
+}
+
+#[derive(Debug)]
+enum State {
+    Vacant,
+    Working,
+    Sleeping { date: NaiveDateTime, line: usize },
+}
+
+/// Every shift in a log, in chronological order.
+#[derive(Debug, Clone, Default)]
+pub struct GuardLog {
+    shifts: Vec<Shift>,
 }
 
-fn compute_stats(input: &str) -> HashMap<ID, Vec<u32>> {
-    let mut state = State::Vacant;
-    let mut stats = HashMap::new();
+impl GuardLog {
+    /// Parses a log, skipping any lines that can not be used.
+    pub fn from_input(input: &str) -> GuardLog {
+        GuardLog::parse_tolerant(input).log
+    }
+
+    /// Parses a log, failing with every line that can not be used. See `parse_tolerant`.
+    pub fn parse(input: &str) -> Result<ParseReport, Vec<LogError>> {
+        let report = GuardLog::parse_tolerant(input);
 
-    let actions = actions_iter(input).sorted_by_key(Action::date);
+        if report.skipped.is_empty() {
+            Ok(report)
+        } else {
+            Err(report.skipped)
+        }
+    }
+
+    /// Parses a log, with its entries in any order and any amount of whitespace between the
+    /// parts of an entry. Lines that can not be used are skipped, and reported with the log.
+    pub fn parse_tolerant(input: &str) -> ParseReport {
+        let mut errors = Vec::new();
+        let mut actions = Vec::new();
 
-    for action in actions {
-        state = match (state, action) {
-            (_, Action::BeginShift { id, date }) => {
-                // Start working
-                State::Working { id, date }
+        for (index, text) in input.lines().enumerate() {
+            let line = index + 1;
+
+            if text.trim().is_empty() {
+                continue;
             }
 
-            (State::Working { id, .. }, Action::FallAsleep { date }) => {
-                // Go to sleep
-                State::Sleeping { id, date }
+            match parse_action(text.trim()) {
+                Ok(action) => actions.push((line, action)),
+                Err(kind) => errors.push(LogError { line, kind }),
             }
+        }
 
-            (State::Sleeping { id, date: sleep_date }, Action::WakeUp { date: wake_date }) => {
-                let hourly_stats = stats.entry(id).or_insert_with(|| vec![0; 60]);
-                let mut current_date = sleep_date;
+        // Entries at the same time stay in the order of the log
+        actions.sort_by_key(|(_, action)| action.date());
 
-                while current_date < wake_date {
-                    hourly_stats[current_date.time().minute() as usize] += 1;
-                    current_date += *ONE_MINUTE;
+        let mut state = State::Vacant;
+        let mut shifts = Vec::<Shift>::new();
+        let mut warnings = Vec::new();
+
+        for (line, action) in actions {
+            state = match (state, action) {
+                (state, Action::BeginShift { id, date }) => {
+                    if let Some(previous) = shifts.last_mut() {
+                        if date < previous.end() {
+                            let previous = previous.id;
+                            warnings.push(Warning::OverlappingShifts { line, previous, next: id });
+                        }
+
+                        end_shift(previous, state, Some(date), &mut warnings);
+                    }
+
+                    // Start working
+                    shifts.push(Shift { id, start: date, sleeps: Vec::new() });
+                    State::Working
+                }
+
+                (State::Working, Action::FallAsleep { date }) => {
+                    // Go to sleep
+                    State::Sleeping { date, line }
                 }
 
-                // Go back to work
-                State::Working { id, date: wake_date }
+                (State::Sleeping { date: sleep_date, .. }, Action::WakeUp { date: wake_date }) => {
+                    shifts.last_mut().unwrap().sleeps.push(sleep_date..wake_date);
+
+                    // Go back to work
+                    State::Working
+                }
+
+                (state, action) => {
+                    let kind = match (&state, action) {
+                        (State::Vacant, _) => LogErrorKind::NoGuardOnDuty,
+                        (_, Action::FallAsleep { .. }) => LogErrorKind::AlreadyAsleep,
+                        _ => LogErrorKind::AlreadyAwake,
+                    };
+
+                    errors.push(LogError { line, kind });
+                    state
+                }
             }
+        }
 
-            (state, action) => panic!("Illegal state transition: {:?}, {:?}", state, action),
+        if let Some(shift) = shifts.last_mut() {
+            end_shift(shift, state, None, &mut warnings);
         }
+
+        errors.sort_by_key(|error| error.line);
+
+        ParseReport { log: GuardLog { shifts }, skipped: errors, warnings }
+    }
+
+    pub fn shifts(&self) -> &[Shift] {
+        &self.shifts
     }
 
-    stats
+    /// The shifts a guard worked.
+    pub fn shifts_of(&self, id: ID) -> impl Iterator<Item = &Shift> {
+        self.shifts.iter().filter(move |shift| shift.id == id)
+    }
+
+    /// The shifts that cover the midnight hour of a date.
+    pub fn shifts_on(&self, date: NaiveDate) -> impl Iterator<Item = &Shift> {
+        self.shifts.iter().filter(move |shift| shift.date() == date)
+    }
+
+    /// The times any guard was asleep during the shifts of a date.
+    pub fn sleeps_on(&self, date: NaiveDate) -> Vec<Range<NaiveDateTime>> {
+        self.shifts_on(date).flat_map(|shift| shift.sleeps.iter().cloned()).collect()
+    }
+
+    /// The guards whose shift had started but not ended at a time.
+    pub fn on_duty(&self, time: NaiveDateTime) -> Vec<ID> {
+        self.shifts
+            .iter()
+            .filter(|shift| shift.start <= time && time < shift.end())
+            .map(|shift| shift.id)
+            .collect()
+    }
+
+    /// The minutes every guard was asleep in total, including guards that never slept.
+    pub fn total_sleep(&self) -> BTreeMap<ID, u32> {
+        let mut totals = BTreeMap::new();
+
+        for shift in &self.shifts {
+            *totals.entry(shift.id).or_insert(0) += shift.minutes_asleep();
+        }
+
+        totals
+    }
+
+    /// Renders the shifts like the chart in the puzzle description, with a row for every shift
+    /// showing the minutes of the midnight hour the guard was awake (`.`) or asleep (`#`).
+    pub fn timeline(&self) -> String {
+        let id_width = self.shifts.iter().map(|shift| shift.id.to_string().len() + 1).max();
+        let id_width = id_width.unwrap_or(0).max(2) + 2;
+
+        let mut lines = vec![format!("{:<7}{:<2$}Minute", "Date", "ID", id_width)];
+
+        for digits in &[|minute: u32| minute / 10, |minute: u32| minute % 10] {
+            lines.push(format!(
+                "{:<1$}{2}",
+                "",
+                7 + id_width,
+                (0..60).map(|minute| digits(minute).to_string()).join("")
+            ));
+        }
+
+        for shift in &self.shifts {
+            let midnight = shift.date().and_hms(0, 0, 0);
+
+            lines.push(format!(
+                "{:<7}{:<2$}{3}",
+                shift.date().format("%m-%d"),
+                format!("#{}", shift.id),
+                id_width,
+                (0..60)
+                    .map(|minute| shift.is_asleep(midnight + *ONE_MINUTE * minute))
+                    .map(|asleep| if asleep { '#' } else { '.' })
+                    .collect::<String>()
+            ));
+        }
+
+        lines.join("\n")
+    }
+
+    /// Exports every time a guard was asleep as CSV, with a header row.
+    pub fn sleeps_csv(&self) -> String {
+        let mut csv = String::from("date,id,start,end,minutes\n");
+
+        for shift in &self.shifts {
+            for sleep in &shift.sleeps {
+                csv += &format!(
+                    "{},{},{},{},{}\n",
+                    shift.date(),
+                    shift.id,
+                    sleep.start.format("%Y-%m-%d %H:%M"),
+                    sleep.end.format("%Y-%m-%d %H:%M"),
+                    (sleep.end - sleep.start).num_minutes()
+                );
+            }
+        }
+
+        csv
+    }
+
+    /// How many times a guard was asleep during each minute of the hour.
+    pub fn minute_histogram(&self, id: ID) -> [u32; 60] {
+        let mut histogram = [0; 60];
+
+        for minute in self.shifts_of(id).flat_map(Shift::minutes_asleep_iter) {
+            histogram[minute.minute() as usize] += 1;
+        }
+
+        histogram
+    }
+
+    /// The minute of the hour a guard was asleep the most, and how many times, or `None` if the
+    /// guard never slept. Ties go to the earliest minute.
+    pub fn most_slept_minute(&self, id: ID) -> Option<(u32, u32)> {
+        let histogram = self.minute_histogram(id);
+
+        (0..60)
+            .map(|minute| (minute, histogram[minute as usize]))
+            .filter(|&(_, frequency)| frequency > 0)
+            .max_by_key(|&(minute, frequency)| (frequency, std::cmp::Reverse(minute)))
+    }
+}
+
+/// Ends a shift when the next one begins, or when the log ends. A guard who is still asleep
+/// sleeps until the shift is over, or until the next shift begins if that is earlier.
+fn end_shift(
+    shift: &mut Shift,
+    state: State,
+    next_start: Option<NaiveDateTime>,
+    warnings: &mut Vec<Warning>,
+) {
+    if let State::Sleeping { date, line } = state {
+        let end = next_start.map_or(shift.end(), |start| start.min(shift.end()));
+
+        if date < end {
+            shift.sleeps.push(date..end);
+        }
+
+        warnings.push(Warning::DanglingSleep { line, id: shift.id });
+    }
+}
+
+fn parse_action(text: &str) -> Result<Action, LogErrorKind> {
+    let caps =
+        ENTRY_PATTERN.captures(text).ok_or_else(|| LogErrorKind::InvalidEntry(text.to_string()))?;
+
+    let timestamp = format!("{} {}", &caps[1], &caps[2]);
+    let date = NaiveDateTime::parse_from_str(&timestamp, "%Y-%m-%d %H:%M")
+        .map_err(|_| LogErrorKind::InvalidTimestamp(timestamp))?;
+
+    let event = &caps[3];
+
+    if let Some(caps) = GUARD_PATTERN.captures(event) {
+        let id = caps[1].parse().map_err(|_| LogErrorKind::UnknownEvent(event.to_string()))?;
+        Ok(Action::BeginShift { date, id })
+    } else if SLEEP_PATTERN.is_match(event) {
+        Ok(Action::FallAsleep { date })
+    } else if WAKE_PATTERN.is_match(event) {
+        Ok(Action::WakeUp { date })
+    } else {
+        Err(LogErrorKind::UnknownEvent(event.to_string()))
+    }
 }
 
 pub fn part1(input: &str) -> u32 {
-    let stats = compute_stats(input);
+    let log = GuardLog::from_input(input);
 
-    let (best_guard_id, best_guard_stats) = stats
+    let (best_guard_id, _) = log
+        .total_sleep()
         .into_iter()
-        .max_by_key(|(_, guard_stats)| guard_stats.iter().sum::<u32>())
+        .max_by_key(|&(_, minutes)| minutes)
         .expect("No best guard found!");
 
-    let best_minute = best_guard_stats
-        .into_iter()
-        .enumerate()
-        .max_by_key(|(_, x)| *x)
-        .map(|(i, _)| i as u32)
-        .expect("No best minute found");
+    let (best_minute, _) = log.most_slept_minute(best_guard_id).expect("No best minute found");
 
     best_guard_id * best_minute
 }
 
 pub fn part2(input: &str) -> u32 {
-    let stats = compute_stats(input);
+    let log = GuardLog::from_input(input);
 
-    let (best_minute, best_guard_id) = (0..60)
-        .cartesian_product(stats.iter())
-        .max_by_key(|(i, (_, guard_stats))| guard_stats[*i])
-        .map(|(i, (id, _))| (i as u32, id))
+    let (best_guard_id, (best_minute, _)) = log
+        .total_sleep()
+        .keys()
+        .filter_map(|&id| Some((id, log.most_slept_minute(id)?)))
+        .max_by_key(|&(_, (_, frequency))| frequency)
         .expect("No best minute or guard found");
 
     best_minute * best_guard_id
@@ -129,6 +456,209 @@ mod tests {
     const TEST_INPUT: &str = include_str!("test_input");
     const INPUT: &str = include_str!("input");
 
+    fn time(input: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(input, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn guard_stats_work() {
+        let log = GuardLog::from_input(TEST_INPUT);
+
+        assert_eq!(log.shifts().len(), 5);
+        assert_eq!(log.total_sleep().into_iter().collect::<Vec<_>>(), vec![(10, 50), (99, 30)]);
+        assert_eq!(log.most_slept_minute(10), Some((24, 2)));
+        assert_eq!(log.most_slept_minute(99), Some((45, 3)));
+        assert_eq!(log.most_slept_minute(42), None);
+        assert_eq!(log.minute_histogram(99)[36..46], [1, 1, 1, 1, 2, 2, 2, 2, 2, 3]);
+    }
+
+    #[test]
+    fn shift_queries_work() {
+        let log = GuardLog::from_input(TEST_INPUT);
+
+        let dates = log.shifts_of(99).map(Shift::date).collect::<Vec<_>>();
+        assert_eq!(
+            dates,
+            vec![
+                NaiveDate::from_ymd(1518, 11, 2),
+                NaiveDate::from_ymd(1518, 11, 4),
+                NaiveDate::from_ymd(1518, 11, 5)
+            ]
+        );
+        assert_eq!(log.shifts_of(10).count(), 2);
+
+        assert_eq!(
+            log.sleeps_on(NaiveDate::from_ymd(1518, 11, 1)),
+            vec![
+                time("1518-11-01 00:05")..time("1518-11-01 00:25"),
+                time("1518-11-01 00:30")..time("1518-11-01 00:55")
+            ]
+        );
+        assert_eq!(
+            log.sleeps_on(NaiveDate::from_ymd(1518, 11, 2)),
+            vec![time("1518-11-02 00:40")..time("1518-11-02 00:50")]
+        );
+        assert!(log.sleeps_on(NaiveDate::from_ymd(1518, 11, 6)).is_empty());
+
+        assert_eq!(log.on_duty(time("1518-11-01 00:59")), vec![10]);
+        assert_eq!(log.on_duty(time("1518-11-01 01:00")), vec![]);
+        assert_eq!(log.on_duty(time("1518-11-01 23:59")), vec![99]);
+        assert_eq!(log.on_duty(time("1518-11-03 00:04")), vec![]);
+        assert_eq!(log.on_duty(time("1518-11-03 00:05")), vec![10]);
+    }
+
+    #[test]
+    fn timeline_works() {
+        let log = GuardLog::from_input(TEST_INPUT);
+
+        assert_eq!(
+            log.timeline(),
+            "\
+Date   ID   Minute
+            000000000011111111112222222222333333333344444444445555555555
+            012345678901234567890123456789012345678901234567890123456789
+11-01  #10  .....####################.....#########################.....
+11-02  #99  ........................................##########..........
+11-03  #10  ........................#####...............................
+11-04  #99  ....................................##########..............
+11-05  #99  .............................................##########....."
+        );
+
+        // A guard that begins before midnight is on the chart of the next day, and the minutes
+        // asleep before midnight are not part of it
+        let log = GuardLog::from_input(
+            "[1518-12-31 23:58] Guard #1234 begins shift\n\
+             [1518-12-31 23:59] falls asleep\n\
+             [1519-01-01 00:02] wakes up\n",
+        );
+
+        let timeline = log.timeline();
+        let rows = timeline.lines().collect::<Vec<_>>();
+
+        assert_eq!(rows[0], "Date   ID     Minute");
+        assert_eq!(rows[3], format!("01-01  #1234  ##{}", ".".repeat(58)));
+    }
+
+    #[test]
+    fn csv_export_works() {
+        let log = GuardLog::from_input(TEST_INPUT);
+        let csv = log.sleeps_csv();
+        let rows = csv.lines().collect::<Vec<_>>();
+
+        assert_eq!(rows.len(), 7);
+        assert_eq!(rows[0], "date,id,start,end,minutes");
+        assert_eq!(rows[1], "1518-11-01,10,1518-11-01 00:05,1518-11-01 00:25,20");
+        assert_eq!(rows[3], "1518-11-02,99,1518-11-02 00:40,1518-11-02 00:50,10");
+    }
+
+    #[test]
+    fn tolerant_parsing_works() {
+        let input = "\
+[1518-11-01  00:00]   Guard  #10 begins shift
+[1518-11-01 00:05]falls asleep
+
+[1518-11-01 00:61] wakes up
+[1518-11-01 00:25] dozes off
+1518-11-01 00:26 wakes up
+[1518-11-01 00:30] wakes up
+[1518-11-01 00:40] wakes   up
+[ 1518-11-01 00:35 ] falls asleep
+[1518-10-31 23:00] falls asleep
+[1518-11-01 00:50] wakes up
+[1518-11-01 00:36] falls asleep
+";
+
+        let report = GuardLog::parse_tolerant(input);
+
+        assert_eq!(
+            report.log.shifts()[0].sleeps,
+            vec![
+                time("1518-11-01 00:05")..time("1518-11-01 00:30"),
+                time("1518-11-01 00:35")..time("1518-11-01 00:40")
+            ]
+        );
+
+        // The line numbers of entries are from before they are sorted
+        assert_eq!(
+            report.skipped.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![
+                "line 4: invalid timestamp `1518-11-01 00:61`",
+                "line 5: unknown event `dozes off`",
+                "line 6: invalid entry `1518-11-01 00:26 wakes up`",
+                "line 10: no guard is on duty",
+                "line 11: the guard is already awake",
+                "line 12: the guard is already asleep",
+            ]
+        );
+        assert!(report.warnings.is_empty());
+
+        let errors = GuardLog::parse(input).unwrap_err();
+        assert_eq!(errors, report.skipped);
+
+        assert_eq!(GuardLog::parse(INPUT).map(|report| report.warnings), Ok(vec![]));
+    }
+
+    #[test]
+    fn irregular_shifts_are_flagged() {
+        let input = "\
+[1518-11-01 00:00] Guard #10 begins shift
+[1518-11-01 00:05] falls asleep
+[1518-11-01 23:58] Guard #99 begins shift
+[1518-11-02 00:10] falls asleep
+[1518-11-02 00:30] Guard #10 begins shift
+[1518-11-02 00:45] falls asleep
+";
+
+        let report = GuardLog::parse(input).unwrap();
+        let sleeps =
+            report.log.shifts().iter().map(|shift| shift.sleeps.clone()).collect::<Vec<_>>();
+
+        assert_eq!(
+            sleeps,
+            vec![
+                vec![time("1518-11-01 00:05")..time("1518-11-01 01:00")],
+                vec![time("1518-11-02 00:10")..time("1518-11-02 00:30")],
+                vec![time("1518-11-02 00:45")..time("1518-11-02 01:00")],
+            ]
+        );
+        assert_eq!(
+            report.warnings,
+            vec![
+                Warning::DanglingSleep { line: 2, id: 10 },
+                Warning::OverlappingShifts { line: 5, previous: 99, next: 10 },
+                Warning::DanglingSleep { line: 4, id: 99 },
+                Warning::DanglingSleep { line: 6, id: 10 },
+            ]
+        );
+        assert_eq!(
+            report.warnings[1].to_string(),
+            "line 5: guard #10 begins before the shift of guard #99 ends"
+        );
+    }
+
+    #[test]
+    fn only_the_midnight_hour_counts() {
+        let log = GuardLog::from_input(
+            "[1518-11-01 23:20] Guard #10 begins shift\n\
+             [1518-11-01 23:30] falls asleep\n\
+             [1518-11-02 00:10] wakes up\n\
+             [1518-11-02 00:50] falls asleep\n\
+             [1518-11-02 01:20] wakes up\n",
+        );
+
+        let histogram = log.minute_histogram(10);
+
+        assert_eq!(log.total_sleep().get(&10), Some(&20));
+        assert!(histogram[..10].iter().all(|&count| count == 1));
+        assert!(histogram[10..50].iter().all(|&count| count == 0));
+        assert!(histogram[50..].iter().all(|&count| count == 1));
+        assert_eq!(log.most_slept_minute(10), Some((0, 1)));
+
+        let timeline = log.timeline();
+        let row = timeline.lines().nth(3).unwrap();
+        assert_eq!(row.matches('#').count(), 1 + 20);
+    }
+
     #[test]
     fn part1_works() {
         assert_eq!(part1(TEST_INPUT), 240);
@@ -1,43 +1,185 @@
-fn reacts(a: char, b: char) -> bool {
-    a.is_ascii_lowercase() != b.is_ascii_lowercase() && a.eq_ignore_ascii_case(&b)
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::io::{self, BufRead, BufReader, Read};
+
+/// Decides whether two adjacent units, in this order, react and destroy each other.
+pub trait Reaction<U> {
+    fn reacts(&self, left: &U, right: &U) -> bool;
+}
+
+impl<U, F> Reaction<U> for F
+where
+    F: Fn(&U, &U) -> bool,
+{
+    fn reacts(&self, left: &U, right: &U) -> bool {
+        self(left, right)
+    }
+}
+
+/// The type of a unit, which is the same for both of its polarities.
+fn unit_type(unit: char) -> char {
+    unit.to_lowercase().next().unwrap_or(unit)
+}
+
+/// The reaction from the puzzle, where units of the same type and opposite polarities react,
+/// with the polarity given by the case of any Unicode letter.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OppositeCase;
+
+impl Reaction<char> for OppositeCase {
+    fn reacts(&self, &left: &char, &right: &char) -> bool {
+        left.is_lowercase() != right.is_lowercase() && unit_type(left) == unit_type(right)
+    }
+}
+
+/// A reaction given by an explicit set of ordered pairs, like an opening and a closing bracket.
+#[derive(Debug, Clone)]
+pub struct PairTable<U> {
+    pairs: HashSet<(U, U)>,
+}
+
+impl<U: Clone + Eq + Hash> PairTable<U> {
+    pub fn new() -> PairTable<U> {
+        PairTable { pairs: HashSet::new() }
+    }
+
+    /// Makes `left` react with a `right` that follows it, but not the other way around.
+    pub fn with_pair(mut self, left: U, right: U) -> PairTable<U> {
+        self.pairs.insert((left, right));
+        self
+    }
+
+    /// Makes `a` and `b` react in either order.
+    pub fn with_symmetric_pair(self, a: U, b: U) -> PairTable<U> {
+        self.with_pair(a.clone(), b.clone()).with_pair(b, a)
+    }
+}
+
+impl<U: Clone + Eq + Hash> Default for PairTable<U> {
+    fn default() -> PairTable<U> {
+        PairTable::new()
+    }
+}
+
+impl<U: Clone + Eq + Hash> Reaction<U> for PairTable<U> {
+    fn reacts(&self, left: &U, right: &U) -> bool {
+        self.pairs.contains(&(left.clone(), right.clone()))
+    }
+}
+
+/// Splits input into units from a set of possibly multi-character units, preferring the longest
+/// unit at every position. Fails with the byte offset of the first input no unit matches.
+pub fn split_units<'a>(input: &'a str, units: &[&str]) -> Result<Vec<&'a str>, usize> {
+    let mut units = units.iter().filter(|unit| !unit.is_empty()).collect::<Vec<_>>();
+    units.sort_by_key(|unit| std::cmp::Reverse(unit.len()));
+
+    let mut result = Vec::new();
+    let mut offset = 0;
+
+    while offset < input.len() {
+        let unit = units.iter().find(|unit| input[offset..].starts_with(**unit)).ok_or(offset)?;
+        result.push(&input[offset..offset + unit.len()]);
+        offset += unit.len();
+    }
+
+    Ok(result)
 }
 
-fn collapse(input: &str) -> impl Iterator<Item = char> {
-    let mut chars: Vec<_> = input.chars().filter(char::is_ascii_alphabetic).collect();
-    let mut i: usize = 0;
+/// Reduces a polymer one unit at a time. Since the polymer has no reactions left, a new unit can
+/// only react with the last unit, which then exposes the one before it.
+#[derive(Debug, Clone)]
+pub struct Reducer<U, R> {
+    reaction: R,
+    polymer: Vec<U>,
+}
 
-    while i + 1 < chars.len() {
-        if reacts(chars[i], chars[i + 1]) {
-            chars.remove(i);
-            chars.remove(i);
-            if i != 0 {
-                i -= 1
+impl<U, R: Reaction<U>> Reducer<U, R> {
+    pub fn new(reaction: R) -> Reducer<U, R> {
+        Reducer { reaction, polymer: Vec::new() }
+    }
+
+    pub fn push(&mut self, unit: U) {
+        match self.polymer.last() {
+            Some(last) if self.reaction.reacts(last, &unit) => {
+                self.polymer.pop();
             }
-        } else {
-            i += 1;
+            _ => self.polymer.push(unit),
         }
     }
 
-    chars.into_iter()
+    /// The fully reduced polymer so far.
+    pub fn polymer(&self) -> &[U] {
+        &self.polymer
+    }
+
+    pub fn into_polymer(self) -> Vec<U> {
+        self.polymer
+    }
 }
 
-pub fn part1(input: &str) -> usize {
-    collapse(input).count()
+impl<U, R: Reaction<U>> Extend<U> for Reducer<U, R> {
+    fn extend<I: IntoIterator<Item = U>>(&mut self, units: I) {
+        for unit in units {
+            self.push(unit);
+        }
+    }
 }
 
-pub fn part2(input: &str) -> usize {
-    use std::char;
+/// Fully reduces a polymer in a single pass.
+pub fn reduce_with<U>(units: impl IntoIterator<Item = U>, reaction: impl Reaction<U>) -> Vec<U> {
+    let mut reducer = Reducer::new(reaction);
+    reducer.extend(units);
+    reducer.into_polymer()
+}
+
+/// Fully reduces a polymer of letters, ignoring any whitespace.
+pub fn reduce(units: impl IntoIterator<Item = char>) -> Vec<char> {
+    reduce_with(units.into_iter().filter(|unit| !unit.is_whitespace()), OppositeCase)
+}
+
+/// Fully reduces a polymer of letters while reading it, ignoring any whitespace.
+pub fn reduce_reader(reader: impl Read) -> io::Result<Vec<char>> {
+    let mut reducer = Reducer::new(OppositeCase);
 
-    let all_units = (0x61..=0x7a).filter_map(char::from_u32);
+    for line in BufReader::new(reader).lines() {
+        reducer.extend(line?.chars().filter(|unit| !unit.is_whitespace()));
+    }
+
+    Ok(reducer.into_polymer())
+}
+
+/// The result of removing every unit of a type from a polymer before reducing it.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Removal {
+    pub unit: char,
+    pub length: usize,
+}
 
-    all_units
-        .map(|unit| {
-            let filtered_input = input.replace(|c: char| c.eq_ignore_ascii_case(&unit), "");
+/// Finds the unit type whose removal gives the shortest polymer, preferring the lowest type on
+/// ties, or `None` if the polymer is empty.
+///
+/// Removing units never creates reactions that were not possible before, so the polymer can
+/// just as well be reduced first, which makes it a lot shorter to go through for every unit type.
+pub fn shortest_removal(polymer: &[char]) -> Option<Removal> {
+    let mut unit_types = polymer.iter().cloned().map(unit_type).collect::<Vec<_>>();
+    unit_types.sort();
+    unit_types.dedup();
 
-            collapse(&filtered_input).count()
+    unit_types
+        .into_iter()
+        .map(|unit| Removal {
+            unit,
+            length: reduce(polymer.iter().cloned().filter(|&c| unit_type(c) != unit)).len(),
         })
-        .min()
-        .expect("No solution found!")
+        .min_by_key(|removal| removal.length)
+}
+
+pub fn part1(input: &str) -> usize {
+    reduce(input.chars()).len()
+}
+
+pub fn part2(input: &str) -> usize {
+    shortest_removal(&reduce(input.chars())).map_or(0, |removal| removal.length)
 }
 
 #[cfg(test)]
@@ -56,6 +198,63 @@ mod tests {
         assert_eq!(part1(INPUT), 9562);
     }
 
+    #[test]
+    fn reducing_works() {
+        let polymer = reduce("dabAcCaCBAcCcaDA".chars());
+        assert_eq!(polymer.into_iter().collect::<String>(), "dabCBAcaDA");
+
+        let polymer = reduce_reader("dabAcCaCBAcCcaDA\n".as_bytes()).unwrap();
+        assert_eq!(polymer.into_iter().collect::<String>(), "dabCBAcaDA");
+
+        assert_eq!(reduce_reader(INPUT.as_bytes()).unwrap().len(), 9562);
+    }
+
+    #[test]
+    fn shortest_removal_works() {
+        let polymer = reduce("dabAcCaCBAcCcaDA".chars());
+        assert_eq!(shortest_removal(&polymer), Some(Removal { unit: 'c', length: 4 }));
+
+        let polymer = reduce(INPUT.chars());
+        assert_eq!(shortest_removal(&polymer).map(|removal| removal.length), Some(4934));
+        assert_eq!(shortest_removal(&[]), None);
+    }
+
+    #[test]
+    fn unicode_units_work() {
+        let polymer = reduce("ÉaéAÀà ßẞΣσ".chars());
+        assert_eq!(polymer.into_iter().collect::<String>(), "ÉaéA");
+
+        let polymer = reduce_reader("ΣαΑ\nσ".as_bytes()).unwrap();
+        assert!(polymer.is_empty());
+    }
+
+    #[test]
+    fn pair_tables_work() {
+        let brackets = PairTable::new().with_pair('(', ')').with_pair('[', ']').with_pair('{', '}');
+
+        assert!(reduce_with("([{}]())".chars(), brackets.clone()).is_empty());
+        assert_eq!(reduce_with("(]".chars(), brackets.clone()), vec!['(', ']']);
+        assert_eq!(reduce_with(")(".chars(), brackets), vec![')', '(']);
+
+        let spins = PairTable::new().with_symmetric_pair('↑', '↓');
+        assert_eq!(reduce_with("↑↓↓↑↑↑".chars(), spins), vec!['↑', '↑']);
+    }
+
+    #[test]
+    fn multi_character_units_work() {
+        let tags = ["<b>", "</b>", "<br>", "<i>", "</i>"];
+        let units = split_units("<b><i></i><br></b><b>", &tags).unwrap();
+        assert_eq!(units, vec!["<b>", "<i>", "</i>", "<br>", "</b>", "<b>"]);
+        assert_eq!(split_units("<b><u>", &tags), Err(3));
+
+        let tag_pairs = PairTable::new().with_pair("<b>", "</b>").with_pair("<i>", "</i>");
+        assert_eq!(reduce_with(units, tag_pairs), vec!["<b>", "<br>", "</b>", "<b>"]);
+
+        // Any relation works, like tokens that cancel out
+        let tokens = vec![3, 1, -1, -3, 2, 5];
+        assert_eq!(reduce_with(tokens, |a: &i32, b: &i32| a + b == 0), vec![2, 5]);
+    }
+
     #[test]
     fn part2_works() {
         assert_eq!(part2(INPUT), 4934);
@@ -1,52 +1,191 @@
-use std::collections::VecDeque;
+use crate::ring::Ring;
+use std::num::ParseIntError;
+use std::str::FromStr;
 
-trait Rotate<N> {
-    fn rotate(&mut self, n: N);
+/// A game of marbles, where players take turns placing the marbles `1..=last_marble`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct MarbleGame {
+    pub player_count: usize,
+    pub last_marble: usize,
 }
 
-impl<T> Rotate<isize> for VecDeque<T> {
-    fn rotate(&mut self, n: isize) {
-        if !self.is_empty() {
-            use std::cmp::Ordering;
-            match n.cmp(&0) {
-                Ordering::Greater => {
-                    for _ in 0..n {
-                        let front = self.pop_front().unwrap();
-                        self.push_back(front);
-                    }
-                }
-                Ordering::Less => {
-                    for _ in 0..-n {
-                        let back = self.pop_back().unwrap();
-                        self.push_front(back);
-                    }
-                }
-                _ => {}
+/// A turn where the player kept their marble, and took another one from the circle.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ScoringTurn {
+    pub player: usize,
+    pub marble: usize,
+    pub removed: usize,
+}
+
+impl ScoringTurn {
+    pub fn points(&self) -> usize {
+        self.marble + self.removed
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct GameResult {
+    /// The score of every player, where player `i` places the marbles `i + 1`, `i + 1 + n`, ...
+    pub scores: Vec<usize>,
+    /// The player with the highest score, or the first one of them if several are tied.
+    pub winner: usize,
+    pub scoring_turns: Vec<ScoringTurn>,
+}
+
+impl GameResult {
+    pub fn high_score(&self) -> usize {
+        self.scores[self.winner]
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ParseGameError {
+    InvalidFormat,
+    NoPlayers,
+    Int(ParseIntError),
+}
+
+impl From<ParseIntError> for ParseGameError {
+    fn from(error: ParseIntError) -> ParseGameError {
+        ParseGameError::Int(error)
+    }
+}
+
+impl FromStr for MarbleGame {
+    type Err = ParseGameError;
+
+    /// Parses a game like "10 players; last marble is worth 1618 points".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use lazy_static::lazy_static;
+        use regex::Regex;
+
+        lazy_static! {
+            static ref GAME_PATTERN: Regex =
+                Regex::new(r"^(\d+) players?; last marble is worth (\d+) points?$").unwrap();
+        }
+
+        let caps = GAME_PATTERN.captures(s.trim()).ok_or(ParseGameError::InvalidFormat)?;
+        let player_count = caps[1].parse()?;
+
+        if player_count == 0 {
+            return Err(ParseGameError::NoPlayers);
+        }
+
+        Ok(MarbleGame { player_count, last_marble: caps[2].parse()? })
+    }
+}
+
+/// Places a marble in the circle, returning the marble that was removed if it was a scoring turn.
+fn place_marble(marbles: &mut Ring<usize>, marble: usize) -> Option<usize> {
+    if marble % 23 == 0 {
+        marbles.counter_clockwise(7);
+        marbles.remove()
+    } else {
+        marbles.clockwise(1);
+        marbles.insert(marble);
+        None
+    }
+}
+
+impl MarbleGame {
+    pub fn new(player_count: usize, last_marble: usize) -> MarbleGame {
+        assert!(player_count > 0, "A game needs at least one player");
+        MarbleGame { player_count, last_marble }
+    }
+
+    /// The player who places a marble, counting from 0.
+    fn player(&self, marble: usize) -> usize {
+        (marble - 1) % self.player_count
+    }
+
+    pub fn play(&self) -> GameResult {
+        let mut scores = vec![0; self.player_count];
+        let mut scoring_turns = Vec::new();
+        let mut marbles = Ring::with_capacity(self.last_marble + 1);
+
+        marbles.insert(0);
+
+        for marble in 1..=self.last_marble {
+            if let Some(removed) = place_marble(&mut marbles, marble) {
+                let turn = ScoringTurn { player: self.player(marble), marble, removed };
+                scores[turn.player] += turn.points();
+                scoring_turns.push(turn);
             }
         }
+
+        let winner = (0..self.player_count)
+            .max_by_key(|&player| (scores[player], std::cmp::Reverse(player)))
+            .unwrap();
+
+        GameResult { scores, winner, scoring_turns }
+    }
+
+    /// Renders the circle after every turn, like in the puzzle description. Every line renders
+    /// the whole circle, so this is only meant for small games.
+    pub fn replay(&self) -> Replay {
+        Replay { game: *self, marbles: Ring::new(), next_marble: 0 }
+    }
+}
+
+pub struct Replay {
+    game: MarbleGame,
+    marbles: Ring<usize>,
+    next_marble: usize,
+}
+
+impl Replay {
+    /// The circle, starting at marble 0, with the current marble in parentheses.
+    fn render(&self, marble: usize) -> String {
+        let width = self.game.last_marble.to_string().len().max(2);
+        let mut circle = self.marbles.iter().cloned().collect::<Vec<_>>();
+        let current = self.marbles.current().cloned();
+
+        let zero = circle.iter().position(|&marble| marble == 0).unwrap_or(0);
+        circle.rotate_left(zero);
+
+        let mut line = circle
+            .iter()
+            .flat_map(|marble| format!(" {:>1$}", marble, width).into_bytes())
+            .collect::<Vec<_>>();
+        line.push(b' ');
+
+        // The parentheses go right around the digits of the current marble
+        if let Some(index) = circle.iter().position(|&marble| Some(marble) == current) {
+            let end = (index + 1) * (width + 1);
+            line[end - current.unwrap().to_string().len() - 1] = b'(';
+            line[end] = b')';
+        }
+
+        let player = match marble {
+            0 => "-".to_string(),
+            marble => (self.game.player(marble) + 1).to_string(),
+        };
+
+        format!("[{}]{}", player, String::from_utf8(line).unwrap())
     }
 }
 
-pub fn max_score(player_count: usize, last_marble_value: usize) -> usize {
-    let mut player_scores = vec![0; player_count];
-    let mut marbles = VecDeque::with_capacity(last_marble_value);
-
-    marbles.push_front(0);
-
-    for marble_number in 1..=last_marble_value {
-        if marble_number % 23 == 0 {
-            marbles.rotate(7);
-            let player_index = marble_number % player_count;
-            let removed_marble_number = marbles.pop_front().unwrap();
-            player_scores[player_index] += marble_number + removed_marble_number;
-            marbles.rotate(-1)
+impl Iterator for Replay {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        let marble = self.next_marble;
+
+        if marble > self.game.last_marble {
+            return None;
+        } else if marble == 0 {
+            self.marbles.insert(0);
         } else {
-            marbles.rotate(-1);
-            marbles.push_front(marble_number);
+            place_marble(&mut self.marbles, marble);
         }
+
+        self.next_marble += 1;
+        Some(self.render(marble))
     }
+}
 
-    player_scores.into_iter().max().unwrap()
+pub fn max_score(player_count: usize, last_marble_value: usize) -> usize {
+    MarbleGame::new(player_count, last_marble_value).play().high_score()
 }
 
 #[cfg(test)]
@@ -66,6 +205,68 @@ mod tests {
         assert_eq!(max_score(30, 5807), 37305);
     }
 
+    #[test]
+    fn parsing_works() {
+        let game =
+            "491 players; last marble is worth 71058 points\n".parse::<MarbleGame>().unwrap();
+        assert_eq!(game, MarbleGame::new(PLAYER_COUNT, MAX_MARBLE_VALUE));
+
+        assert!("1 player; last marble is worth 1 point".parse::<MarbleGame>().is_ok());
+        assert_eq!(
+            "0 players; last marble is worth 25 points".parse::<MarbleGame>(),
+            Err(ParseGameError::NoPlayers)
+        );
+        assert_eq!(
+            "9 players; last marble is worth points".parse::<MarbleGame>(),
+            Err(ParseGameError::InvalidFormat)
+        );
+    }
+
+    #[test]
+    fn results_work() {
+        let result = MarbleGame::new(9, 25).play();
+
+        assert_eq!(result.winner, 4);
+        assert_eq!(result.scores, vec![0, 0, 0, 0, 32, 0, 0, 0, 0]);
+        assert_eq!(result.scoring_turns, vec![ScoringTurn { player: 4, marble: 23, removed: 9 }]);
+
+        let result = MarbleGame::new(10, 1618).play();
+
+        assert_eq!(result.high_score(), 8317);
+        assert_eq!(result.scoring_turns.len(), 1618 / 23);
+        assert_eq!(
+            result.scores.iter().sum::<usize>(),
+            result.scoring_turns.iter().map(ScoringTurn::points).sum::<usize>()
+        );
+
+        // Without any scoring turns, everyone is tied
+        assert_eq!(MarbleGame::new(3, 5).play().winner, 0);
+    }
+
+    #[test]
+    fn replaying_works() {
+        let lines = MarbleGame::new(9, 25).replay().collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 26);
+        assert_eq!(lines[0], "[-] (0)");
+        assert_eq!(lines[1], "[1]  0 (1)");
+        assert_eq!(lines[2], "[2]  0 (2) 1 ");
+        assert_eq!(lines[3], "[3]  0  2  1 (3)");
+        assert_eq!(lines[10], "[1]  0  8  4  9  2(10) 5  1  6  3  7 ");
+        assert_eq!(
+            lines[22],
+            "[4]  0 16  8 17  4 18  9 19  2 20 10 21  5(22)11  1 12  6 13  3 14  7 15 "
+        );
+        assert_eq!(
+            lines[23],
+            "[5]  0 16  8 17  4 18(19) 2 20 10 21  5 22 11  1 12  6 13  3 14  7 15 "
+        );
+        assert_eq!(
+            lines[25],
+            "[7]  0 16  8 17  4 18 19  2 24 20(25)10 21  5 22 11  1 12  6 13  3 14  7 15 "
+        );
+    }
+
     #[test]
     fn part1() {
         assert_eq!(max_score(PLAYER_COUNT, MAX_MARBLE_VALUE), 361_466);
@@ -1,98 +1,305 @@
+use crate::automaton::PackedRow;
+use core::fmt;
 use itertools::Itertools;
-use std::collections::BTreeSet;
+use std::collections::{BTreeSet, HashMap};
 
-type Rule = ([bool; 5], bool);
+/// The widest neighborhood supported, which keeps the rule table reasonably small.
+const MAX_RADIUS: usize = 8;
 
-fn parse_input(input: &str) -> (BTreeSet<isize>, Vec<Rule>) {
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum RuleErrorKind {
+    MissingInitialState,
+    InvalidLine(String),
+    /// A rule whose neighborhood has no center pot.
+    EvenWidth(usize),
+    /// A rule that is wider or narrower than the first rule.
+    MismatchedWidth {
+        expected: usize,
+        found: usize,
+    },
+    TooWide(usize),
+    Duplicate(String),
+    Contradiction(String),
+    /// The rules cover some of the neighborhoods that make a pot empty, but not all of them.
+    Incomplete(Vec<String>),
+    /// An empty neighborhood grows a plant, which fills the infinitely many empty pots at once.
+    InfiniteGrowth,
+}
+
+/// An error found while parsing the input, with the (1-based) line it was found on, if any.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RuleError {
+    pub line: Option<usize>,
+    pub kind: RuleErrorKind,
+}
+
+impl fmt::Display for RuleErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuleErrorKind::MissingInitialState => write!(f, "missing initial state"),
+            RuleErrorKind::InvalidLine(line) => write!(f, "invalid line `{}`", line),
+            RuleErrorKind::EvenWidth(width) => {
+                write!(f, "neighborhood of {} pots has no center", width)
+            }
+            RuleErrorKind::MismatchedWidth { expected, found } => {
+                write!(f, "expected a neighborhood of {} pots, found {}", expected, found)
+            }
+            RuleErrorKind::TooWide(width) => {
+                write!(f, "neighborhood of {} pots is wider than {}", width, 2 * MAX_RADIUS + 1)
+            }
+            RuleErrorKind::Duplicate(pattern) => write!(f, "duplicate rule for `{}`", pattern),
+            RuleErrorKind::Contradiction(pattern) => {
+                write!(f, "contradicting rules for `{}`", pattern)
+            }
+            RuleErrorKind::Incomplete(missing) => {
+                write!(f, "no rules for {}", missing.iter().map(|p| format!("`{}`", p)).join(", "))
+            }
+            RuleErrorKind::InfiniteGrowth => {
+                write!(f, "empty pots grow plants, so the number of plants is infinite")
+            }
+        }
+    }
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.kind),
+            None => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+/// The rules for every neighborhood of `2 * radius + 1` pots, read as bits from the leftmost pot
+/// in the highest bit to the rightmost pot in the lowest bit.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RuleSet {
+    radius: usize,
+    table: Vec<bool>,
+}
+
+impl RuleSet {
+    pub fn radius(&self) -> usize {
+        self.radius
+    }
+}
+
+fn pattern_to_string(window: usize, width: usize) -> String {
+    (0..width).rev().map(|bit| if window >> bit & 1 == 1 { '#' } else { '.' }).collect()
+}
+
+/// Parses and validates the initial state and the rules.
+///
+/// The puzzle examples only list the rules that grow plants, so when no rule empties a pot, the
+/// missing ones are taken to mean just that. Otherwise every neighborhood needs a rule.
+fn parse_input(input: &str) -> Result<(BTreeSet<isize>, RuleSet), RuleError> {
     use lazy_static::lazy_static;
     use regex::Regex;
 
     lazy_static! {
-        static ref HEADER_PATTERN: Regex = Regex::new(r"initial state: ([#\.]+)").unwrap();
-        static ref RULE_PATTERN: Regex =
-            Regex::new(r"([#\.])([#\.])([#\.])([#\.])([#\.]) => ([#\.])").unwrap();
+        static ref HEADER_PATTERN: Regex = Regex::new(r"^initial state:\s*([#\.]*)$").unwrap();
+        static ref RULE_PATTERN: Regex = Regex::new(r"^([#\.]+)\s*=>\s*([#\.])$").unwrap();
     }
 
-    let initial_state = HEADER_PATTERN
-        .captures_iter(input)
-        .filter_map(|caps| {
-            Some(
-                caps.get(1)?
-                    .as_str()
+    let error = |line, kind| RuleError { line, kind };
+
+    let mut initial_state = None;
+    let mut width = None;
+    let mut rules = HashMap::<usize, (bool, usize)>::new();
+
+    for (line, text) in input.lines().enumerate().map(|(i, text)| (i + 1, text.trim())) {
+        if text.is_empty() {
+            continue;
+        }
+
+        if let Some(caps) = HEADER_PATTERN.captures(text) {
+            initial_state = Some(
+                caps[1]
                     .chars()
                     .enumerate()
                     .filter_map(|(i, c)| if c == '#' { Some(i as isize) } else { None })
                     .collect::<BTreeSet<_>>(),
-            )
-        })
-        .nth(0)
-        .expect("It has a header");
-
-    let rules = RULE_PATTERN
-        .captures_iter(input)
-        // .inspect(|caps| println!("{:?}", &caps))
-        .filter_map(|caps| {
-            Some((
-                [
-                    caps.get(1)?.as_str() == "#",
-                    caps.get(2)?.as_str() == "#",
-                    caps.get(3)?.as_str() == "#",
-                    caps.get(4)?.as_str() == "#",
-                    caps.get(5)?.as_str() == "#",
-                ],
-                caps.get(6)?.as_str() == "#",
-            ))
-        })
-        .collect();
-
-    (initial_state, rules)
-}
-
-fn front_and_back<'a, T: Clone>(data: &'a BTreeSet<T>) -> (Option<&'a T>, Option<&'a T>) {
-    let mut it = data.iter();
-    let first = it.next();
-    let last = it.next_back().or(first);
-    (first, last)
-}
-
-pub fn find_pattern_sum(input: &str, generations: usize) -> Option<i64> {
-    let (initial_state, rules) = parse_input(input);
-
-    (0..)
-        .scan(initial_state, |state, i| {
-            if let (Some(first), Some(last)) = front_and_back(&state) {
-                *state = (first - 2..last + 2)
-                    .filter(|i| {
-                        rules.iter().cloned().any(|(rule, should_insert)| {
-                            should_insert
-                                && [
-                                    state.contains(&(i - 2)),
-                                    state.contains(&(i - 1)),
-                                    state.contains(&i),
-                                    state.contains(&(i + 1)),
-                                    state.contains(&(i + 2)),
-                                ] == rule
-                        })
-                    })
-                    .collect();
-                Some((i, state.iter().map(|x| *x as i64).sum()))
-            } else {
-                None
+            );
+            continue;
+        }
+
+        let caps = RULE_PATTERN
+            .captures(text)
+            .ok_or_else(|| error(Some(line), RuleErrorKind::InvalidLine(text.to_string())))?;
+
+        let pattern = &caps[1];
+        let expected = *width.get_or_insert(pattern.len());
+
+        if pattern.len() != expected {
+            let kind = RuleErrorKind::MismatchedWidth { expected, found: pattern.len() };
+            return Err(error(Some(line), kind));
+        } else if expected % 2 == 0 {
+            return Err(error(Some(line), RuleErrorKind::EvenWidth(expected)));
+        } else if expected > 2 * MAX_RADIUS + 1 {
+            return Err(error(Some(line), RuleErrorKind::TooWide(expected)));
+        }
+
+        let window = pattern.chars().fold(0, |window, pot| window << 1 | (pot == '#') as usize);
+        let should_insert = &caps[2] == "#";
+
+        match rules.insert(window, (should_insert, line)) {
+            Some((previous, _)) if previous == should_insert => {
+                return Err(error(Some(line), RuleErrorKind::Duplicate(pattern.to_string())));
             }
-        })
-        .tuple_windows::<(_, _, _, _)>()
-        .find_map(|((_, sum_a), (_, sum_b), (_, sum_c), (i, sum_d))| {
-            // Stop either when we reach the generation
-            if i + 1 == generations {
-                Some(sum_d)
-            // Or when we find a sum that increases by the same amount for three turns
-            } else if sum_d - sum_c == sum_c - sum_b && sum_c - sum_b == sum_b - sum_a {
-                println!("Repeating sum {} at {}", sum_b - sum_a, i);
-                Some(sum_d + (sum_d - sum_c) * (generations - 1 - i) as i64)
-            } else {
-                None
+            Some(_) => {
+                return Err(error(Some(line), RuleErrorKind::Contradiction(pattern.to_string())));
             }
-        })
+            None => {}
+        }
+    }
+
+    let initial_state =
+        initial_state.ok_or_else(|| error(None, RuleErrorKind::MissingInitialState))?;
+
+    // Without any rules, nothing ever grows
+    let width = width.unwrap_or(1);
+    let mut table = vec![false; 1 << width];
+
+    for (&window, &(should_insert, _)) in &rules {
+        table[window] = should_insert;
+    }
+
+    if rules.values().any(|&(should_insert, _)| !should_insert) {
+        let missing = (0..table.len())
+            .filter(|window| !rules.contains_key(window))
+            .map(|window| pattern_to_string(window, width))
+            .collect::<Vec<_>>();
+
+        if !missing.is_empty() {
+            return Err(error(None, RuleErrorKind::Incomplete(missing)));
+        }
+    }
+
+    if table[0] {
+        let line = rules.get(&0).map(|&(_, line)| line);
+        return Err(error(line, RuleErrorKind::InfiniteGrowth));
+    }
+
+    Ok((initial_state, RuleSet { radius: width / 2, table }))
+}
+
+/// A snapshot of a row of pots, packed into bits. It's normalized so that the first bit is the
+/// leftmost pot with a plant, which makes two rows with the same pattern equal apart from their
+/// offsets.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Pots {
+    /// The number of the leftmost pot with a plant.
+    offset: i128,
+    /// Bit `i` is set if pot `offset + i` has a plant. The last word is never zero.
+    bits: Vec<u64>,
+}
+
+impl Pots {
+    fn get(&self, bit: usize) -> bool {
+        self.bits.get(bit / 64).map_or(false, |word| word >> (bit % 64) & 1 == 1)
+    }
+
+    /// The number of bits up to and including the rightmost plant.
+    fn len(&self) -> usize {
+        self.bits.last().map_or(0, |word| self.bits.len() * 64 - word.leading_zeros() as usize)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits.is_empty()
+    }
+
+    /// The number of the leftmost pot with a plant, if any.
+    pub fn first(&self) -> Option<i128> {
+        Some(self.offset).filter(|_| !self.is_empty())
+    }
+
+    pub fn contains(&self, pot: i128) -> bool {
+        pot >= self.offset && self.get((pot - self.offset) as usize)
+    }
+
+    /// The numbers of the pots with plants, from left to right.
+    pub fn plants(&self) -> impl Iterator<Item = i128> + '_ {
+        (0..self.len()).filter(move |&bit| self.get(bit)).map(move |bit| self.offset + bit as i128)
+    }
+
+    pub fn count(&self) -> usize {
+        self.bits.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    pub fn sum(&self) -> i128 {
+        self.plants().sum()
+    }
+
+    fn shifted(&self, by: i128) -> Pots {
+        Pots { offset: self.offset + by, bits: self.bits.clone() }
+    }
+}
+
+/// Shows the pots from the leftmost plant to the rightmost one.
+impl fmt::Display for Pots {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for bit in 0..self.len() {
+            write!(f, "{}", if self.get(bit) { '#' } else { '.' })?;
+        }
+        Ok(())
+    }
+}
+
+/// Every generation of pots, starting with the initial state.
+pub struct Generations {
+    row: PackedRow,
+}
+
+impl Iterator for Generations {
+    type Item = Pots;
+
+    fn next(&mut self) -> Option<Pots> {
+        let pots = Pots { offset: self.row.offset() as i128, bits: self.row.words().to_vec() };
+        self.row.step();
+        Some(pots)
+    }
+}
+
+pub fn generations(input: &str) -> Result<Generations, RuleError> {
+    let (initial_state, RuleSet { radius, table }) = parse_input(input)?;
+
+    Ok(Generations { row: PackedRow::new(initial_state, radius, table) })
+}
+
+/// The pots after a number of generations.
+///
+/// Once a pattern repeats, every later generation is an earlier one, shifted by however far the
+/// pattern drifted over the whole periods in between, so this is exact for any number of
+/// generations.
+pub fn generation(input: &str, generations: u64) -> Result<Pots, RuleError> {
+    let mut history = Vec::<Pots>::new();
+    let mut seen = HashMap::<Vec<u64>, usize>::new();
+
+    for pots in self::generations(input)? {
+        if history.len() as u64 == generations {
+            return Ok(pots);
+        }
+
+        if let Some(&start) = seen.get(&pots.bits) {
+            let period = (history.len() - start) as u64;
+            let drift = pots.offset - history[start].offset;
+
+            let remaining = generations - start as u64;
+            let cycles = (remaining / period) as i128;
+
+            return Ok(history[start + (remaining % period) as usize].shifted(cycles * drift));
+        }
+
+        seen.insert(pots.bits.clone(), history.len());
+        history.push(pots);
+    }
+
+    unreachable!()
+}
+
+/// The sum of the numbers of the pots with plants after a number of generations.
+pub fn find_pattern_sum(input: &str, generations: u64) -> Result<i128, RuleError> {
+    Ok(generation(input, generations)?.sum())
 }
 
 #[cfg(test)]
@@ -102,14 +309,132 @@ mod tests {
     const INPUT: &str = include_str!("input");
     const TEST_INPUT: &str = include_str!("test_input");
 
+    /// Simulates every generation, one pot at a time.
+    fn brute_force_sum(input: &str, generations: u64) -> i128 {
+        let (mut state, rules) = parse_input(input).unwrap();
+        let radius = rules.radius() as isize;
+
+        for _ in 0..generations {
+            let (first, last) = match (state.iter().next(), state.iter().next_back()) {
+                (Some(&first), Some(&last)) => (first, last),
+                _ => break,
+            };
+
+            state = (first - radius..=last + radius)
+                .filter(|i| {
+                    let window = (i - radius..=i + radius)
+                        .fold(0, |window, i| window << 1 | state.contains(&i) as usize);
+                    rules.table[window]
+                })
+                .collect();
+        }
+
+        state.iter().map(|&i| i as i128).sum()
+    }
+
+    #[test]
+    fn sums_are_exact() {
+        // Alternates between one and two plants, drifting one pot to the right every other
+        // generation
+        let oscillating = "initial state: #\n\n..#.. => #\n...#. => #\n##... => #\n";
+
+        for &input in &[TEST_INPUT, INPUT, oscillating] {
+            for generations in (0..300).chain(vec![997, 1000, 1001]) {
+                assert_eq!(
+                    find_pattern_sum(input, generations),
+                    Ok(brute_force_sum(input, generations))
+                );
+            }
+        }
+
+        let big_sum = find_pattern_sum(TEST_INPUT, u64::max_value()).unwrap();
+        let sum = find_pattern_sum(TEST_INPUT, 1000).unwrap();
+        let next_sum = find_pattern_sum(TEST_INPUT, 1001).unwrap();
+
+        assert_eq!(big_sum, sum + (next_sum - sum) * (u64::max_value() - 1000) as i128);
+
+        let k = (u64::max_value() / 2) as i128;
+        assert_eq!(find_pattern_sum(oscillating, u64::max_value() - 1), Ok(k));
+        assert_eq!(find_pattern_sum(oscillating, u64::max_value()), Ok(2 * k - 1));
+    }
+
+    #[test]
+    fn generations_work() {
+        let rows = generations(TEST_INPUT).unwrap().take(21).collect::<Vec<_>>();
+
+        assert_eq!(rows[0].to_string(), "#..#.#..##......###...###");
+        assert_eq!(rows[1].to_string(), "#...#....#.....#..#..#..#");
+        assert_eq!(rows[20].first(), Some(-2));
+        assert_eq!(rows[20].to_string(), "#....##....#####...#######....#.#..##");
+        assert_eq!(rows[20].count(), 19);
+        assert!(rows[20].contains(3) && !rows[20].contains(2));
+
+        assert_eq!(generation(TEST_INPUT, 20), Ok(rows[20].clone()));
+
+        // The packed row only keeps the words between the leftmost and rightmost plants, however
+        // far they drift
+        let mut generations = generations(INPUT).unwrap();
+        let pots = generations.nth(1000).unwrap();
+        assert_eq!(generations.row.generation(), 1001);
+        assert_eq!(pots.bits.len(), (pots.len() + 63) / 64);
+        assert_eq!(generation(INPUT, 50_000_000_000).unwrap().first(), Some(49_999_999_912));
+    }
+
+    #[test]
+    fn rules_are_validated() {
+        let kind = |input: &str| parse_input(input).map(|_| ()).map_err(|error| error.kind);
+        let line = |input: &str| parse_input(input).unwrap_err().line;
+
+        assert_eq!(kind("initial state: #\n\n#.# => #\n"), Ok(()));
+        assert_eq!(kind("#.# => #\n"), Err(RuleErrorKind::MissingInitialState));
+        assert_eq!(
+            kind("initial state: #\n#.# => #\n#..# => #"),
+            Err(RuleErrorKind::MismatchedWidth { expected: 3, found: 4 })
+        );
+        assert_eq!(kind("initial state: #\n#..# => #"), Err(RuleErrorKind::EvenWidth(4)));
+        assert_eq!(
+            kind("initial state: #\n#.# => #\n#.# => #"),
+            Err(RuleErrorKind::Duplicate("#.#".to_string()))
+        );
+        assert_eq!(
+            kind("initial state: #\n#.# => #\n#.# => ."),
+            Err(RuleErrorKind::Contradiction("#.#".to_string()))
+        );
+        assert_eq!(line("initial state: #\n#.# => #\n\n#.# => ."), Some(4));
+        assert_eq!(
+            kind("initial state: #\n### => .\n#.# => #\n..# => #\n.#. => #\n#.. => .\n... => ."),
+            Err(RuleErrorKind::Incomplete(vec![".##".to_string(), "##.".to_string()]))
+        );
+        assert_eq!(kind("initial state: #\n... => #"), Err(RuleErrorKind::InfiniteGrowth));
+        assert_eq!(
+            kind("initial state: #\nfoo"),
+            Err(RuleErrorKind::InvalidLine("foo".to_string()))
+        );
+        assert!(kind(INPUT).is_ok());
+    }
+
+    #[test]
+    fn other_radiuses_work() {
+        // Rule 90, where a pot has a plant if exactly one of its neighbors had one
+        let rule_90 = "initial state: #\n\n#.. => #\n..# => #\n#.# => .\n... => .\n##. => #\n.## => #\n### => .\n.#. => .\n";
+
+        let rows =
+            generations(rule_90).unwrap().take(4).map(|pots| pots.to_string()).collect::<Vec<_>>();
+        assert_eq!(rows, vec!["#", "#.#", "#...#", "#.#.#.#"]);
+
+        // Plants that jump three pots to the right every generation
+        let jumping = "initial state: #\n\n#...... => #\n";
+        assert_eq!(find_pattern_sum(jumping, 1_000_000), Ok(3_000_000));
+    }
+
     #[test]
     fn part1_works() {
-        assert_eq!(find_pattern_sum(TEST_INPUT, 20), Some(325));
-        assert_eq!(find_pattern_sum(INPUT, 20), Some(3230));
+        assert_eq!(find_pattern_sum(TEST_INPUT, 20), Ok(325));
+        assert_eq!(find_pattern_sum(INPUT, 20), Ok(3230));
     }
 
     #[test]
     fn part2_works() {
-        assert_eq!(find_pattern_sum(INPUT, 50_000_000_000), Some(4_400_000_000_304));
+        assert_eq!(find_pattern_sum(INPUT, 50_000_000_000), Ok(4_400_000_000_304));
     }
 }
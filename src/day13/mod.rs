@@ -1,19 +1,20 @@
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 
 #[derive(Debug, Eq, PartialEq, Copy, Clone, Hash, Ord, PartialOrd)]
 pub struct Position {
-    y: usize,
-    x: usize,
+    pub y: usize,
+    pub x: usize,
 }
 
 impl Position {
-    fn moved_by(self, direction: Direction) -> Position {
+    /// The neighboring position in a direction, or `None` when moving off the top or left edge.
+    fn moved_by(self, direction: Direction) -> Option<Position> {
         let Position { x, y } = self;
         match direction {
-            Direction::Up => Position { x, y: y - 1 },
-            Direction::Down => Position { x, y: y + 1 },
-            Direction::Left => Position { x: x - 1, y },
-            Direction::Right => Position { x: x + 1, y },
+            Direction::Up => Some(Position { x, y: y.checked_sub(1)? }),
+            Direction::Down => Some(Position { x, y: y + 1 }),
+            Direction::Left => Some(Position { x: x.checked_sub(1)?, y }),
+            Direction::Right => Some(Position { x: x + 1, y }),
         }
     }
 }
@@ -27,7 +28,32 @@ enum Track {
     Intersection,
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+impl Track {
+    /// Whether the track can lead in a direction. Curves can lead anywhere, depending on which
+    /// way they are connected.
+    fn can_lead(self, direction: Direction) -> bool {
+        match self {
+            Track::Vertical => direction == Direction::Up || direction == Direction::Down,
+            Track::Horizontal => direction == Direction::Left || direction == Direction::Right,
+            Track::CurveRight | Track::CurveLeft | Track::Intersection => true,
+        }
+    }
+
+    /// The possible sets of directions the track connects.
+    fn connections(self) -> &'static [&'static [Direction]] {
+        use self::Direction::*;
+
+        match self {
+            Track::Vertical => &[&[Up, Down]],
+            Track::Horizontal => &[&[Left, Right]],
+            Track::CurveRight => &[&[Down, Right], &[Up, Left]],
+            Track::CurveLeft => &[&[Down, Left], &[Up, Right]],
+            Track::Intersection => &[&[Up, Down, Left, Right]],
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub enum Direction {
     Up,
     Down,
@@ -35,22 +61,34 @@ pub enum Direction {
     Right,
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+impl Direction {
+    fn opposite(self) -> Direction {
+        match self {
+            Direction::Up => Direction::Down,
+            Direction::Down => Direction::Up,
+            Direction::Left => Direction::Right,
+            Direction::Right => Direction::Left,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 enum NextIntersection {
     GoStraight,
     TurnLeft,
     TurnRight,
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
 struct Cart {
+    id: usize,
     direction: Direction,
     next_intersection: NextIntersection,
 }
 
 impl Cart {
-    fn new(direction: Direction) -> Cart {
-        Cart { direction, next_intersection: NextIntersection::TurnLeft }
+    fn new(id: usize, direction: Direction) -> Cart {
+        Cart { id, direction, next_intersection: NextIntersection::TurnLeft }
     }
 
     fn turned_by(&self, track: Track) -> Cart {
@@ -97,7 +135,7 @@ impl Cart {
             }
         };
 
-        Cart { direction, next_intersection }
+        Cart { id: self.id, direction, next_intersection }
     }
 }
 
@@ -137,19 +175,19 @@ fn parse(input: &str) -> (HashMap<Position, Track>, BTreeMap<Position, Cart>) {
                     tracks.insert(position, Track::Intersection);
                 }
                 'v' => {
-                    carts.insert(position, Cart::new(Direction::Down));
+                    carts.insert(position, Cart::new(carts.len(), Direction::Down));
                     tracks.insert(position, Track::Vertical);
                 }
                 '^' => {
-                    carts.insert(position, Cart::new(Direction::Up));
+                    carts.insert(position, Cart::new(carts.len(), Direction::Up));
                     tracks.insert(position, Track::Vertical);
                 }
                 '<' => {
-                    carts.insert(position, Cart::new(Direction::Left));
+                    carts.insert(position, Cart::new(carts.len(), Direction::Left));
                     tracks.insert(position, Track::Horizontal);
                 }
                 '>' => {
-                    carts.insert(position, Cart::new(Direction::Right));
+                    carts.insert(position, Cart::new(carts.len(), Direction::Right));
                     tracks.insert(position, Track::Horizontal);
                 }
                 _ => {}
@@ -160,6 +198,91 @@ fn parse(input: &str) -> (HashMap<Position, Track>, BTreeMap<Position, Cart>) {
     (tracks, carts)
 }
 
+/// A problem with the layout of the tracks.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TrackError {
+    /// A piece of track that leads to a tile which doesn't lead back.
+    DanglingSegment(Position),
+    /// A cart on a piece of track that leads to a tile which doesn't lead back.
+    InvalidCartTile(Position),
+}
+
+/// Why a question about the carts couldn't be answered.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SimulationError {
+    /// A cart ran off the tracks at a position.
+    Derailed(Position),
+    /// The carts will never collide.
+    NoCollision,
+    /// Every cart crashed.
+    NoSurvivor,
+    /// The carts that are left will never collide, so this many survive.
+    MultipleSurvivors(usize),
+}
+
+/// Checks that every piece of track connects to its neighbors, in reading order.
+///
+/// A curve connects one of two pairs of directions, so the connections of every piece of track
+/// whose neighbors can't lead back at all are ruled out first. A piece of track is then only valid
+/// if the remaining connections of its neighbors lead back to it. Neighbors without any remaining
+/// connection are reported on their own, so they only need to be able to lead back.
+fn validate(
+    tracks: &HashMap<Position, Track>,
+    carts: &BTreeMap<Position, Cart>,
+) -> Vec<TrackError> {
+    let can_lead_back = |position: Position, direction: Direction| {
+        position
+            .moved_by(direction)
+            .and_then(|neighbor| tracks.get(&neighbor))
+            .map_or(false, |track| track.can_lead(direction.opposite()))
+    };
+
+    let connections = tracks
+        .iter()
+        .map(|(&position, track)| {
+            let connections = track
+                .connections()
+                .iter()
+                .cloned()
+                .filter(|directions| {
+                    directions.iter().all(|&direction| can_lead_back(position, direction))
+                })
+                .collect::<Vec<_>>();
+            (position, connections)
+        })
+        .collect::<HashMap<_, _>>();
+
+    let leads_back = |position: Position, direction: Direction| match position
+        .moved_by(direction)
+        .and_then(|neighbor| connections.get(&neighbor))
+    {
+        Some(theirs) if !theirs.is_empty() => {
+            theirs.iter().any(|directions| directions.contains(&direction.opposite()))
+        }
+        Some(_) => can_lead_back(position, direction),
+        None => false,
+    };
+
+    let mut positions = tracks.keys().cloned().collect::<Vec<_>>();
+    positions.sort();
+
+    positions
+        .into_iter()
+        .filter(|position| {
+            !connections[position].iter().any(|directions| {
+                directions.iter().all(|&direction| leads_back(*position, direction))
+            })
+        })
+        .map(|position| {
+            if carts.contains_key(&position) {
+                TrackError::InvalidCartTile(position)
+            } else {
+                TrackError::DanglingSegment(position)
+            }
+        })
+        .collect()
+}
+
 #[allow(dead_code)]
 fn draw(tracks: &HashMap<Position, Track>, carts: &BTreeMap<Position, Cart>) {
     use core::cmp::{max, min};
@@ -205,54 +328,151 @@ fn draw(tracks: &HashMap<Position, Track>, carts: &BTreeMap<Position, Cart>) {
     }
 }
 
-pub fn part1(input: &str) -> Position {
-    let (tracks, mut carts) = parse(input);
+/// Two or more carts crashing into each other, after which they are removed from the tracks.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Collision {
+    /// The tick during which the carts crashed, counting from 1.
+    pub tick: usize,
+    pub position: Position,
+    /// The ids of the carts involved, which are numbered in reading order of the input.
+    pub carts: Vec<usize>,
+}
+
+pub struct MineCartSystem {
+    tracks: HashMap<Position, Track>,
+    carts: BTreeMap<Position, Cart>,
+    ticks: usize,
+}
+
+impl MineCartSystem {
+    /// Parses and validates the tracks, returning every problem found.
+    pub fn from_input(input: &str) -> Result<MineCartSystem, Vec<TrackError>> {
+        let (tracks, carts) = parse(input);
+        let errors = validate(&tracks, &carts);
+
+        if errors.is_empty() {
+            Ok(MineCartSystem { tracks, carts, ticks: 0 })
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// The number of ticks so far.
+    pub fn ticks(&self) -> usize {
+        self.ticks
+    }
 
-    loop {
-        let mut next_carts = BTreeMap::new();
+    /// The position and direction of every remaining cart, in the order they move.
+    pub fn carts(&self) -> impl Iterator<Item = (Position, Direction)> + '_ {
+        self.carts.iter().map(|(&position, cart)| (position, cart.direction))
+    }
+
+    /// Moves every cart once, returning the collisions that happened along the way. A cart that
+    /// runs off the tracks stops the tick halfway.
+    pub fn tick(&mut self) -> Result<Vec<Collision>, SimulationError> {
+        let mut carts = std::mem::replace(&mut self.carts, BTreeMap::new());
+        let mut collisions = Vec::new();
+
+        self.ticks += 1;
 
         while let Some((position, cart)) = carts.pop() {
-            let track = tracks.get(&position).cloned().expect("Derailed cart!");
-            let cart = cart.turned_by(track);
-            let position = position.moved_by(cart.direction);
+            let track = match self.tracks.get(&position) {
+                Some(&track) => track,
+                None => {
+                    self.carts.append(&mut carts);
+                    self.carts.insert(position, cart);
+                    return Err(SimulationError::Derailed(position));
+                }
+            };
 
-            if carts.remove(&position).or_else(|| next_carts.remove(&position)).is_some() {
-                return position;
+            let cart = cart.turned_by(track);
+            let position = match position.moved_by(cart.direction) {
+                Some(position) => position,
+                None => {
+                    self.carts.append(&mut carts);
+                    self.carts.insert(position, cart);
+                    return Err(SimulationError::Derailed(position));
+                }
+            };
+
+            if let Some(other) = carts.remove(&position).or_else(|| self.carts.remove(&position)) {
+                collisions.push(Collision {
+                    tick: self.ticks,
+                    position,
+                    carts: vec![cart.id, other.id],
+                });
             } else {
-                next_carts.insert(position, cart);
+                self.carts.insert(position, cart);
             }
         }
 
-        carts = next_carts;
+        Ok(collisions)
     }
-}
 
-pub fn part2(input: &str) -> Position {
-    let (tracks, mut carts) = parse(input);
+    /// All the collisions from now on, in the order they happen. Ends when less than two carts
+    /// remain, when the carts are back in a state they have already been in, since they will
+    /// never collide again then, or after a cart derails.
+    pub fn collisions(&mut self) -> Collisions<'_> {
+        Collisions { system: self, pending: VecDeque::new(), seen: HashSet::new(), done: false }
+    }
 
-    if carts.len() % 2 == 0 {
-        panic!("Even number of carts");
+    pub fn first_collision(&mut self) -> Result<Collision, SimulationError> {
+        self.collisions().next().unwrap_or(Err(SimulationError::NoCollision))
     }
 
-    while 1 < carts.len() {
-        let mut next_carts = BTreeMap::new();
+    /// The position of the last cart left once all others have crashed.
+    pub fn last_cart(&mut self) -> Result<Position, SimulationError> {
+        for collision in self.collisions() {
+            collision?;
+        }
 
-        while let Some((position, cart)) = carts.pop() {
-            let track = tracks.get(&position).cloned().expect("Derailed cart!");
-            let cart = cart.turned_by(track);
-            let position = position.moved_by(cart.direction);
+        match self.carts.len() {
+            0 => Err(SimulationError::NoSurvivor),
+            1 => Ok(self.carts().next().unwrap().0),
+            survivors => Err(SimulationError::MultipleSurvivors(survivors)),
+        }
+    }
+}
+
+pub struct Collisions<'a> {
+    system: &'a mut MineCartSystem,
+    pending: VecDeque<Collision>,
+    seen: HashSet<BTreeMap<Position, Cart>>,
+    done: bool,
+}
 
-            if carts.remove(&position).or_else(|| next_carts.remove(&position)).is_none() {
-                next_carts.insert(position, cart);
+impl<'a> Iterator for Collisions<'a> {
+    type Item = Result<Collision, SimulationError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() && !self.done {
+            let system = &mut *self.system;
+            if system.carts.len() < 2 || !self.seen.insert(system.carts.clone()) {
+                self.done = true;
+                break;
+            }
+
+            match system.tick() {
+                Ok(collisions) => self.pending.extend(collisions),
+                Err(error) => {
+                    self.done = true;
+                    return Some(Err(error));
+                }
             }
         }
 
-        carts = next_carts;
+        self.pending.pop_front().map(Ok)
     }
+}
 
-    let (last_position, _) = carts.pop().unwrap();
+pub fn part1(input: &str) -> Position {
+    let mut system = MineCartSystem::from_input(input).unwrap();
+    system.first_collision().unwrap().position
+}
 
-    last_position
+pub fn part2(input: &str) -> Position {
+    let mut system = MineCartSystem::from_input(input).unwrap();
+    system.last_cart().unwrap()
 }
 
 #[cfg(test)]
@@ -263,6 +483,79 @@ mod tests {
     const TEST_INPUT: &str = include_str!("test_input");
     const TEST_INPUT_2: &str = include_str!("test_input_2");
 
+    #[test]
+    fn collisions_work() {
+        let mut system = MineCartSystem::from_input(TEST_INPUT_2).unwrap();
+
+        assert_eq!(system.carts().count(), 9);
+
+        let collisions = system.collisions().collect::<Result<Vec<_>, _>>().unwrap();
+
+        assert_eq!(
+            collisions
+                .iter()
+                .map(|collision| (collision.tick, collision.position))
+                .collect::<Vec<_>>(),
+            vec![
+                (1, Position { x: 2, y: 0 }),
+                (1, Position { x: 2, y: 4 }),
+                (1, Position { x: 6, y: 4 }),
+                (3, Position { x: 2, y: 4 }),
+            ]
+        );
+        assert_eq!(system.ticks(), 3);
+        assert_eq!(
+            system.carts().collect::<Vec<_>>(),
+            vec![(Position { x: 6, y: 4 }, Direction::Up)]
+        );
+
+        let mut system = MineCartSystem::from_input(TEST_INPUT).unwrap();
+        let collision = system.first_collision().unwrap();
+
+        assert_eq!(
+            collision,
+            Collision { tick: 14, position: Position { x: 7, y: 3 }, carts: vec![0, 1] }
+        );
+    }
+
+    #[test]
+    fn validation_works() {
+        assert_eq!(
+            MineCartSystem::from_input("/->-\\\n|   |\n\\-+-/\n  |").err(),
+            Some(vec![
+                TrackError::DanglingSegment(Position { x: 2, y: 2 }),
+                TrackError::DanglingSegment(Position { x: 2, y: 3 })
+            ])
+        );
+        assert_eq!(
+            MineCartSystem::from_input("/-\\\n| |\n\\-/ >").err(),
+            Some(vec![TrackError::InvalidCartTile(Position { x: 4, y: 2 })])
+        );
+
+        // The curve right of the cart can only connect down and right, so the cart can't enter it
+        assert_eq!(
+            MineCartSystem::from_input("/>/\\\n\\-//").err(),
+            Some(vec![
+                TrackError::InvalidCartTile(Position { x: 1, y: 0 }),
+                TrackError::DanglingSegment(Position { x: 3, y: 1 })
+            ])
+        );
+        assert!(MineCartSystem::from_input(INPUT).is_ok());
+        assert!(MineCartSystem::from_input(TEST_INPUT).is_ok());
+        assert!(MineCartSystem::from_input(TEST_INPUT_2).is_ok());
+    }
+
+    #[test]
+    fn survivors_are_reported() {
+        let mut system = MineCartSystem::from_input("/>-<\\\n|   |\n\\---/").unwrap();
+        assert_eq!(system.last_cart(), Err(SimulationError::NoSurvivor));
+
+        let mut system =
+            MineCartSystem::from_input("/>--\\\n|   |\n\\---/\n/>--\\\n|   |\n\\---/").unwrap();
+        assert_eq!(system.first_collision(), Err(SimulationError::NoCollision));
+        assert_eq!(system.last_cart(), Err(SimulationError::MultipleSurvivors(2)));
+    }
+
     #[test]
     fn part1_works() {
         assert_eq!(part1(TEST_INPUT), Position { x: 7, y: 3 });
@@ -1,11 +1,10 @@
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::fmt;
-use std::iter::once;
 
 #[derive(Eq, PartialEq, Copy, Clone, Hash, Ord, PartialOrd)]
 pub struct Position {
-    y: isize,
-    x: isize,
+    pub y: isize,
+    pub x: isize,
 }
 
 impl fmt::Debug for Position {
@@ -14,28 +13,76 @@ impl fmt::Debug for Position {
     }
 }
 
-impl Position {
-    fn is_neighbor(&self, other: Position) -> bool {
-        self.neighbors().any(|position| position == other)
+/// The map letter of the elves in the puzzle.
+pub const ELF: char = 'E';
+
+/// The map letter of the goblins in the puzzle.
+pub const GOBLIN: char = 'G';
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct FactionRules {
+    pub health_points: isize,
+    pub attack_power: isize,
+}
+
+/// The rules of a game. Every letter on the map is a faction, which fights every other faction.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CombatRules {
+    /// The rules of the factions not listed in `factions`.
+    pub default: FactionRules,
+    pub factions: HashMap<char, FactionRules>,
+    /// A unit dies once its hit points drop to this or below.
+    pub death_threshold: isize,
+}
+
+impl Default for CombatRules {
+    fn default() -> CombatRules {
+        CombatRules {
+            default: FactionRules { health_points: 200, attack_power: 3 },
+            factions: HashMap::new(),
+            death_threshold: 0,
+        }
     }
+}
 
-    fn neighbors(self) -> impl Iterator<Item = Position> {
-        let Position { x, y } = self;
-        once(Position { x, y: y - 1 })
-            .chain(once(Position { x, y: y + 1 }))
-            .chain(once(Position { x: x - 1, y }))
-            .chain(once(Position { x: x + 1, y }))
+impl CombatRules {
+    pub fn with_faction(mut self, faction: char, rules: FactionRules) -> CombatRules {
+        self.factions.insert(faction, rules);
+        self
     }
+
+    pub fn with_attack_power(self, faction: char, attack_power: isize) -> CombatRules {
+        let rules = FactionRules { attack_power, ..self.faction(faction) };
+        self.with_faction(faction, rules)
+    }
+
+    pub fn faction(&self, faction: char) -> FactionRules {
+        self.factions.get(&faction).cloned().unwrap_or(self.default)
+    }
+}
+
+/// How a game ended.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Summary {
+    /// The faction of the survivors, if any unit survived.
+    pub winner: Option<char>,
+    pub completed_rounds: isize,
+    pub remaining_health_points: isize,
+    /// The number of surviving units of the winning faction.
+    pub survivors: usize,
+    /// The number of units lost by each faction.
+    pub casualties: BTreeMap<char, usize>,
 }
 
-#[derive(Debug, Eq, PartialEq, Copy, Clone)]
-enum UnitKind {
-    Elf,
-    Goblin,
+impl Summary {
+    pub fn outcome(&self) -> isize {
+        self.completed_rounds * self.remaining_health_points
+    }
 }
 
 struct Unit {
-    kind: UnitKind,
+    id: usize,
+    faction: char,
     position: Position,
     is_alive: bool,
     health_points: isize,
@@ -44,69 +91,404 @@ struct Unit {
 
 impl fmt::Debug for Unit {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}({}) @ {:?}", self.kind, self.health_points, self.position)
+        write!(f, "{}({}) @ {:?}", self.faction, self.health_points, self.position)
     }
 }
 
 #[derive(Debug)]
 enum GameError {
-    ElfDied,
+    ProtectedUnitDied,
     NoSolution,
 }
 
-type GameResult = Result<isize, GameError>;
+type GameResult = Result<Summary, GameError>;
 
-#[allow(dead_code)]
-fn draw(walls: &HashSet<Position>, units: &[Unit]) {
-    use core::cmp::{max, min};
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct UnitState {
+    pub id: usize,
+    pub faction: char,
+    pub position: Position,
+    pub health_points: isize,
+}
 
-    let bounds = walls.iter().fold(None, |current_bounds, position| {
-        let Position { x, y } = *position;
-        if let Some((min_x, min_y, max_x, max_y)) = current_bounds {
-            Some((min(min_x, x), min(min_y, y), max(max_x, x), max(max_y, y)))
-        } else {
-            Some((x, y, x, y))
+/// The board at some point in time, with every living unit.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Snapshot {
+    /// The position of the top left corner of the map.
+    pub origin: Position,
+    pub map: Vec<String>,
+    pub units: Vec<UnitState>,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Attack {
+    pub target: usize,
+    pub damage: isize,
+    pub remaining_health_points: isize,
+    pub killed: bool,
+}
+
+/// What a single unit did during its turn.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Turn {
+    pub unit: usize,
+    pub faction: char,
+    pub from: Position,
+    pub to: Option<Position>,
+    pub attack: Option<Attack>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Round {
+    pub number: isize,
+    pub turns: Vec<Turn>,
+    /// The last round of a game ends early, when a unit finds no targets left.
+    pub completed: bool,
+    pub snapshot: Snapshot,
+}
+
+/// A turn-by-turn record of a game, exportable as text (in the same format as the puzzle's worked
+/// examples) or as JSON.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CombatLog {
+    pub initial: Snapshot,
+    pub rounds: Vec<Round>,
+    pub outcome: Option<isize>,
+}
+
+impl Snapshot {
+    fn new(board: &Board, units: &[Unit]) -> Snapshot {
+        use core::cmp::{max, min};
+
+        let bounds = board.walls().fold(None, |current_bounds, position| {
+            let Position { x, y } = position;
+            if let Some((min_x, min_y, max_x, max_y)) = current_bounds {
+                Some((min(min_x, x), min(min_y, y), max(max_x, x), max(max_y, y)))
+            } else {
+                Some((x, y, x, y))
+            }
+        });
+
+        let mut units = units
+            .iter()
+            .filter(|unit| unit.is_alive)
+            .map(|unit| UnitState {
+                id: unit.id,
+                faction: unit.faction,
+                position: unit.position,
+                health_points: unit.health_points,
+            })
+            .collect::<Vec<_>>();
+
+        units.sort_by_key(|unit| unit.position);
+
+        let unit_positions =
+            units.iter().map(|unit| (unit.position, unit.faction)).collect::<HashMap<_, _>>();
+
+        let origin = bounds.map_or(Position { x: 0, y: 0 }, |(x, y, _, _)| Position { x, y });
+
+        let map = bounds
+            .map(|(min_x, min_y, max_x, max_y)| {
+                (min_y..=max_y)
+                    .map(|y| {
+                        (min_x..=max_x)
+                            .map(|x| {
+                                let position = Position { x, y };
+
+                                if let Some(faction) = unit_positions.get(&position) {
+                                    *faction
+                                } else if board.is_wall(position) {
+                                    '#'
+                                } else {
+                                    '.'
+                                }
+                            })
+                            .collect()
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Snapshot { origin, map, units }
+    }
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (y, line) in self.map.iter().enumerate() {
+            let row_units = self
+                .units
+                .iter()
+                .filter(|unit| unit.position.y == self.origin.y + y as isize)
+                .map(|unit| format!("{}({})", unit.faction, unit.health_points))
+                .collect::<Vec<_>>();
+
+            if row_units.is_empty() {
+                writeln!(f, "{}", line)?;
+            } else {
+                writeln!(f, "{}   {}", line, row_units.join(", "))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Turn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}#{} at {:?}", self.faction, self.unit, self.from)?;
+
+        if let Some(to) = self.to {
+            write!(f, " moves to {:?}", to)?;
+        }
+
+        match self.attack {
+            Some(attack) => {
+                write!(
+                    f,
+                    " attacks #{} for {} ({} left)",
+                    attack.target, attack.damage, attack.remaining_health_points
+                )?;
+                if attack.killed {
+                    write!(f, ", killing it")?;
+                }
+                Ok(())
+            }
+            None if self.to.is_none() => write!(f, " waits"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for CombatLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Initially:")?;
+        write!(f, "{}", self.initial)?;
+
+        for round in &self.rounds {
+            writeln!(f)?;
+            writeln!(f, "Round {}:", round.number)?;
+            for turn in &round.turns {
+                writeln!(f, "  {}", turn)?;
+            }
+
+            writeln!(f)?;
+            if round.completed {
+                let plural = if round.number == 1 { "" } else { "s" };
+                writeln!(f, "After {} round{}:", round.number, plural)?;
+            } else {
+                writeln!(f, "During round {}:", round.number)?;
+            }
+            write!(f, "{}", round.snapshot)?;
         }
-    });
 
-    let units = units
+        if let Some(outcome) = self.outcome {
+            writeln!(f)?;
+            writeln!(f, "Outcome: {}", outcome)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn position_to_json(position: Position) -> String {
+    format!("{{\"x\":{},\"y\":{}}}", position.x, position.y)
+}
+
+fn snapshot_to_json(snapshot: &Snapshot) -> String {
+    let map = snapshot.map.iter().map(|line| format!("\"{}\"", line)).collect::<Vec<_>>();
+    let units = snapshot
+        .units
         .iter()
-        .filter(|unit| unit.is_alive)
-        .map(|unit| (unit.position, unit))
-        .collect::<HashMap<_, _>>();
-
-    if let Some((min_x, min_y, max_x, max_y)) = bounds {
-        for y in min_y..=max_y {
-            let line = (min_x..=max_x)
-                .map(|x| {
-                    let position = Position { x, y };
-
-                    if let Some(unit) = units.get(&position) {
-                        match unit.kind {
-                            UnitKind::Goblin => 'G',
-                            UnitKind::Elf => 'E',
-                        }
-                    } else if walls.contains(&position) {
-                        '#'
-                    } else {
-                        '.'
-                    }
-                })
-                .collect::<String>();
+        .map(|unit| {
+            format!(
+                "{{\"id\":{},\"faction\":\"{}\",\"position\":{},\"health_points\":{}}}",
+                unit.id,
+                unit.faction,
+                position_to_json(unit.position),
+                unit.health_points
+            )
+        })
+        .collect::<Vec<_>>();
+
+    format!("{{\"map\":[{}],\"units\":[{}]}}", map.join(","), units.join(","))
+}
+
+fn turn_to_json(turn: &Turn) -> String {
+    let to = turn.to.map_or_else(|| "null".to_string(), position_to_json);
+    let attack = turn.attack.map_or_else(
+        || "null".to_string(),
+        |attack| {
+            format!(
+                "{{\"target\":{},\"damage\":{},\"remaining_health_points\":{},\"killed\":{}}}",
+                attack.target, attack.damage, attack.remaining_health_points, attack.killed
+            )
+        },
+    );
+
+    format!(
+        "{{\"unit\":{},\"faction\":\"{}\",\"from\":{},\"to\":{},\"attack\":{}}}",
+        turn.unit,
+        turn.faction,
+        position_to_json(turn.from),
+        to,
+        attack
+    )
+}
+
+impl CombatLog {
+    pub fn to_json(&self) -> String {
+        let rounds = self
+            .rounds
+            .iter()
+            .map(|round| {
+                let turns = round.turns.iter().map(turn_to_json).collect::<Vec<_>>();
+                format!(
+                    "{{\"number\":{},\"completed\":{},\"turns\":[{}],\"snapshot\":{}}}",
+                    round.number,
+                    round.completed,
+                    turns.join(","),
+                    snapshot_to_json(&round.snapshot)
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let outcome =
+            self.outcome.map_or_else(|| "null".to_string(), |outcome| outcome.to_string());
+
+        format!(
+            "{{\"initial\":{},\"rounds\":[{}],\"outcome\":{}}}",
+            snapshot_to_json(&self.initial),
+            rounds.join(","),
+            outcome
+        )
+    }
+}
+
+const EMPTY: usize = std::usize::MAX;
+const UNREACHABLE: u32 = std::u32::MAX;
+
+/// The map as a dense grid, where cells are indexed in reading order.
+struct Board {
+    width: usize,
+    height: usize,
+    walls: Vec<bool>,
+    /// The index into `Grid::units` of the unit on each cell, or `EMPTY`.
+    occupants: Vec<usize>,
+}
+
+impl Board {
+    fn index(&self, position: Position) -> usize {
+        position.y as usize * self.width + position.x as usize
+    }
+
+    fn position(&self, index: usize) -> Position {
+        Position { x: (index % self.width) as isize, y: (index / self.width) as isize }
+    }
+
+    fn is_wall(&self, position: Position) -> bool {
+        self.walls[self.index(position)]
+    }
+
+    fn walls(&self) -> impl Iterator<Item = Position> + '_ {
+        (0..self.walls.len()).filter(move |&i| self.walls[i]).map(move |i| self.position(i))
+    }
+
+    fn is_open(&self, index: usize) -> bool {
+        !self.walls[index] && self.occupants[index] == EMPTY
+    }
+
+    /// The neighbors of a cell, in reading order.
+    fn neighbors(&self, index: usize) -> impl Iterator<Item = usize> {
+        let (width, height) = (self.width, self.height);
+        let (x, y) = (index % width, index / width);
 
-            println!("{}", line);
+        let up = Some(index).filter(|_| y > 0).map(|i| i - width);
+        let left = Some(index).filter(|_| x > 0).map(|i| i - 1);
+        let right = Some(index).filter(|_| x + 1 < width).map(|i| i + 1);
+        let down = Some(index).filter(|_| y + 1 < height).map(|i| i + width);
+
+        up.into_iter().chain(left).chain(right).chain(down)
+    }
+}
+
+/// Breadth-first search buffers, reused between searches.
+struct Pathfinder {
+    distances: Vec<u32>,
+    visited: Vec<usize>,
+    queue: VecDeque<usize>,
+}
+
+impl Pathfinder {
+    fn new(size: usize) -> Pathfinder {
+        Pathfinder {
+            distances: vec![UNREACHABLE; size],
+            visited: Vec::new(),
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// Fills `distances` with the distance from the nearest source through open cells, up to
+    /// `max_distance`. Only the cells visited by the previous search need to be reset.
+    fn search(
+        &mut self,
+        board: &Board,
+        sources: impl IntoIterator<Item = usize>,
+        max_distance: u32,
+    ) {
+        for index in self.visited.drain(..) {
+            self.distances[index] = UNREACHABLE;
+        }
+
+        for source in sources {
+            if self.distances[source] == UNREACHABLE {
+                self.distances[source] = 0;
+                self.visited.push(source);
+                self.queue.push_back(source);
+            }
+        }
+
+        while let Some(index) = self.queue.pop_front() {
+            let distance = self.distances[index] + 1;
+            if distance > max_distance {
+                continue;
+            }
+
+            for neighbor in board.neighbors(index) {
+                if board.is_open(neighbor) && self.distances[neighbor] == UNREACHABLE {
+                    self.distances[neighbor] = distance;
+                    self.visited.push(neighbor);
+                    self.queue.push_back(neighbor);
+                }
+            }
         }
     }
 }
 
+/// For every cell, the distance to the nearest cell in range of an enemy of some faction. Only
+/// valid as long as no unit has moved or died since it was computed.
+struct DistanceField {
+    version: u64,
+    pathfinder: Pathfinder,
+}
+
 struct Grid {
-    walls: HashSet<Position>,
+    board: Board,
     units: Vec<Unit>,
+    alive: HashMap<char, usize>,
+    death_threshold: isize,
+    /// Bumped every time a unit moves or dies, to invalidate the distance fields.
+    version: u64,
+    distance_fields: HashMap<char, DistanceField>,
+    pathfinder: Pathfinder,
+    log: Option<CombatLog>,
 }
 
 impl Grid {
-    fn from_input(input: &str, elf_attack_power: isize) -> Grid {
-        let mut walls = HashSet::new();
+    fn from_input(input: &str, rules: &CombatRules) -> Grid {
+        let width = input.lines().map(|line| line.chars().count()).max().unwrap_or(0);
+        let height = input.lines().count();
+        let mut walls = vec![false; width * height];
         let mut units = Vec::new();
 
         for (y, line) in input.lines().enumerate() {
@@ -114,23 +496,16 @@ impl Grid {
                 let position = Position { x: x as isize, y: y as isize };
                 match c {
                     '#' => {
-                        walls.insert(position);
-                    }
-                    'G' => {
-                        units.push(Unit {
-                            kind: UnitKind::Goblin,
-                            position,
-                            health_points: 200,
-                            attack_power: 3,
-                            is_alive: true,
-                        });
+                        walls[y * width + x] = true;
                     }
-                    'E' => {
+                    c if c.is_alphabetic() => {
+                        let FactionRules { health_points, attack_power } = rules.faction(c);
                         units.push(Unit {
-                            kind: UnitKind::Elf,
+                            id: units.len(),
+                            faction: c,
                             position,
-                            health_points: 200,
-                            attack_power: elf_attack_power,
+                            health_points,
+                            attack_power,
                             is_alive: true,
                         });
                     }
@@ -139,158 +514,374 @@ impl Grid {
             }
         }
 
-        Grid { walls, units }
+        let mut alive = HashMap::new();
+        for unit in &units {
+            *alive.entry(unit.faction).or_insert(0) += 1;
+        }
+
+        let board = Board { width, height, walls, occupants: vec![EMPTY; width * height] };
+
+        Grid {
+            pathfinder: Pathfinder::new(board.walls.len()),
+            board,
+            units,
+            alive,
+            death_threshold: rules.death_threshold,
+            version: 0,
+            distance_fields: HashMap::new(),
+            log: None,
+        }
     }
 
-    fn play_game(&mut self, check_elf_death: bool) -> GameResult {
-        for round in 0.. {
-            // println!("");
-            // println!("Round {}", round);
-            // draw(&self.walls, &self.units);
+    fn has_enemies(&self, faction: char) -> bool {
+        self.alive.iter().any(|(&other, &count)| other != faction && count > 0)
+    }
 
+    fn is_enemy_at(&self, faction: char, index: usize) -> bool {
+        let occupant = self.board.occupants[index];
+        occupant != EMPTY && self.units[occupant].faction != faction
+    }
+
+    /// Puts the index of every living unit on the board, which needs to be redone whenever the
+    /// units are reordered.
+    fn place_units(&mut self) {
+        self.board.occupants.iter_mut().for_each(|occupant| *occupant = EMPTY);
+        for (i, unit) in self.units.iter().enumerate().filter(|(_, unit)| unit.is_alive) {
+            let index = self.board.index(unit.position);
+            self.board.occupants[index] = i;
+        }
+    }
+
+    /// Makes sure the distance field towards the cells in range of the enemies of `faction` is up
+    /// to date, recomputing it only if the board has changed since it was last used.
+    fn update_distance_field(&mut self, faction: char) {
+        let size = self.board.walls.len();
+        let field = self.distance_fields.entry(faction).or_insert_with(|| DistanceField {
+            version: std::u64::MAX,
+            pathfinder: Pathfinder::new(size),
+        });
+
+        if field.version != self.version {
+            let board = &self.board;
+            let units = &self.units;
+            let targets = units
+                .iter()
+                .filter(|unit| unit.is_alive && unit.faction != faction)
+                .flat_map(|unit| board.neighbors(board.index(unit.position)))
+                .filter(|&index| board.is_open(index));
+
+            field.pathfinder.search(board, targets, UNREACHABLE);
+            field.version = self.version;
+        }
+    }
+
+    /// Finds the cell the unit should step to. Of the nearest cells in range of an enemy, the
+    /// first in reading order is chosen, and the path to it is traced backwards by always stepping
+    /// to the first neighbor in reading order that is closer to the unit.
+    fn next_step(&mut self, i: usize) -> Option<usize> {
+        let faction = self.units[i].faction;
+        let start = self.board.index(self.units[i].position);
+
+        self.update_distance_field(faction);
+
+        let field = &self.distance_fields[&faction].pathfinder.distances;
+        let distance = self
+            .board
+            .neighbors(start)
+            .map(|neighbor| field[neighbor])
+            .filter(|&distance| distance != UNREACHABLE)
+            .min()?
+            + 1;
+
+        let board = &self.board;
+        let units = &self.units;
+        let pathfinder = &mut self.pathfinder;
+        pathfinder.search(board, Some(start), distance);
+
+        let mut target = pathfinder
+            .visited
+            .iter()
+            .cloned()
+            .filter(|&index| pathfinder.distances[index] == distance)
+            .filter(|&index| {
+                board.neighbors(index).any(|neighbor| {
+                    let occupant = board.occupants[neighbor];
+                    occupant != EMPTY && units[occupant].faction != faction
+                })
+            })
+            .min()?;
+
+        while pathfinder.distances[target] > 1 {
+            let closer = pathfinder.distances[target] - 1;
+            target = board
+                .neighbors(target)
+                .find(|&neighbor| pathfinder.distances[neighbor] == closer)
+                .unwrap();
+        }
+
+        Some(target)
+    }
+
+    fn summary(&self, completed_rounds: isize) -> Summary {
+        let survivors = self.units.iter().filter(|unit| unit.is_alive).collect::<Vec<_>>();
+        let mut casualties = BTreeMap::new();
+
+        for unit in &self.units {
+            let count = casualties.entry(unit.faction).or_insert(0);
+            if !unit.is_alive {
+                *count += 1;
+            }
+        }
+
+        Summary {
+            winner: survivors.first().map(|unit| unit.faction),
+            completed_rounds,
+            remaining_health_points: survivors.iter().map(|unit| unit.health_points).sum(),
+            survivors: survivors.len(),
+            casualties,
+        }
+    }
+
+    fn with_log(mut self) -> Grid {
+        self.log = Some(CombatLog {
+            initial: Snapshot::new(&self.board, &self.units),
+            rounds: Vec::new(),
+            outcome: None,
+        });
+        self
+    }
+
+    fn record_turn(&mut self, round: isize, turn: Turn) {
+        if let Some(log) = &mut self.log {
+            if log.rounds.last().map_or(true, |last| last.number != round) {
+                log.rounds.push(Round {
+                    number: round,
+                    turns: Vec::new(),
+                    completed: false,
+                    snapshot: Snapshot {
+                        origin: log.initial.origin,
+                        map: Vec::new(),
+                        units: Vec::new(),
+                    },
+                });
+            }
+            log.rounds.last_mut().unwrap().turns.push(turn);
+        }
+    }
+
+    fn record_end_of_round(&mut self, round: isize, completed: bool) {
+        if let Some(log) = &mut self.log {
+            if let Some(last) = log.rounds.last_mut().filter(|last| last.number == round) {
+                last.completed = completed;
+                last.snapshot = Snapshot::new(&self.board, &self.units);
+            }
+        }
+    }
+
+    /// Plays until only one faction remains, or aborts when a unit of the `protected` faction dies.
+    fn play_game(&mut self, protected: Option<char>) -> GameResult {
+        for round in 0.. {
             self.units.sort_by_key(|unit| unit.position);
+            self.place_units();
 
-            // To simplify the borrowing, use an index instead of a reference
             for i in 0..self.units.len() {
                 if !self.units[i].is_alive {
                     continue;
                 }
 
-                // println!("");
-                // println!("{:?}:", self.units[i]);
-
-                if self
-                    .units
-                    .iter()
-                    .filter(|target| self.units[i].kind != target.kind && target.is_alive)
-                    .nth(0)
-                    .is_none()
-                {
-                    let sum = self
-                        .units
-                        .iter()
-                        .filter(|unit| unit.is_alive)
-                        .map(|unit| unit.health_points)
-                        .sum::<isize>();
-                    return Ok(round * sum);
-                }
-
-                let occupied = self
-                    .units
-                    .iter()
-                    .enumerate()
-                    .filter(|(j, unit)| i != *j && unit.is_alive)
-                    .map(|(_, unit)| unit.position)
-                    .collect::<HashSet<_>>();
-
-                let in_range = self
-                    .units
-                    .iter()
-                    .filter(|target| self.units[i].kind != target.kind && target.is_alive)
-                    .flat_map(|target| target.position.neighbors())
-                    .filter(|position| {
-                        !self.walls.contains(&position) && !occupied.contains(&position)
-                    })
-                    .collect::<HashSet<_>>();
-
-                // println!("TARGETS IN RANGE: {:?}", &in_range);
+                let faction = self.units[i].faction;
 
-                if !in_range.contains(&self.units[i].position) {
-                    let next_move = {
-                        let mut visiting = VecDeque::new();
-                        let mut seen = HashSet::new();
-                        let mut meta = HashMap::new();
-                        visiting.push_back((self.units[i].position, 0));
+                if !self.has_enemies(faction) {
+                    let summary = self.summary(round);
 
-                        while let Some((position, distance)) = visiting.pop_front() {
-                            for neighbor_position in position.neighbors() {
-                                if self.walls.contains(&position) || occupied.contains(&position) {
-                                    continue;
-                                }
+                    self.record_end_of_round(round + 1, false);
+                    if let Some(log) = &mut self.log {
+                        log.outcome = Some(summary.outcome());
+                    }
 
-                                let meta_entry = (distance + 1, position);
-                                match meta.get(&neighbor_position) {
-                                    Some(best_entry) if *best_entry <= meta_entry => {}
-                                    _ => {
-                                        meta.insert(neighbor_position, meta_entry);
-                                    }
-                                }
+                    return Ok(summary);
+                }
 
-                                if seen.contains(&neighbor_position) {
-                                    continue;
-                                }
+                let from = self.units[i].position;
+                let mut attack = None;
+                let mut protected_unit_died = false;
 
-                                if !visiting.iter().any(|visit| visit.0 == neighbor_position) {
-                                    visiting.push_back((neighbor_position, distance + 1));
-                                }
-                            }
-
-                            seen.insert(position);
-                        }
-
-                        if let Some((_, mut closest_position)) = meta
-                            .iter()
-                            .filter(|(position, _)| in_range.contains(position))
-                            .map(|(position, (distance, _))| (distance, position))
-                            .min()
-                        {
-                            while let Some((_, parent_position)) =
-                                meta.get(closest_position).filter(|(distance, _)| *distance > 1)
-                            {
-                                closest_position = parent_position;
-                            }
-
-                            Some(*closest_position)
-                        } else {
-                            None
-                        }
-                    };
-
-                    if let Some(next_position) = next_move {
-                        // println!("MOVE TO {:?}", next_position);
-                        self.units[i].position = next_position;
+                let start = self.board.index(from);
+                if !self.board.neighbors(start).any(|neighbor| self.is_enemy_at(faction, neighbor))
+                {
+                    if let Some(next) = self.next_step(i) {
+                        self.board.occupants[start] = EMPTY;
+                        self.board.occupants[next] = i;
+                        self.units[i].position = self.board.position(next);
+                        self.version += 1;
                     }
                 }
 
-                if let Some((j, _)) = self
-                    .units
-                    .iter()
-                    .enumerate()
-                    .filter(|(_, target)| {
-                        target.is_alive
-                            && self.units[i].kind != target.kind
-                            && self.units[i].position.is_neighbor(target.position)
-                    })
-                    .min_by_key(|(_, opponent)| (opponent.health_points, opponent.position))
+                let index = self.board.index(self.units[i].position);
+                if let Some(j) = self
+                    .board
+                    .neighbors(index)
+                    .filter(|&neighbor| self.is_enemy_at(faction, neighbor))
+                    .map(|neighbor| self.board.occupants[neighbor])
+                    .min_by_key(|&j| (self.units[j].health_points, self.units[j].position))
                 {
-                    // println!("ATTACK {:?}", self.units[j]);
-
                     let attack_power = self.units[i].attack_power;
                     let opponent = &mut self.units[j];
                     opponent.health_points -= attack_power;
-                    if opponent.health_points < 0 {
-                        // println!("DEATH: {:?}", opponent);
+                    if opponent.health_points <= self.death_threshold {
                         opponent.is_alive = false;
-                        if check_elf_death && opponent.kind == UnitKind::Elf {
-                            return Err(GameError::ElfDied);
-                        }
+                        protected_unit_died = protected == Some(opponent.faction);
                     }
+
+                    attack = Some(Attack {
+                        target: opponent.id,
+                        damage: attack_power,
+                        remaining_health_points: opponent.health_points,
+                        killed: !opponent.is_alive,
+                    });
+
+                    if !opponent.is_alive {
+                        let index = self.board.index(opponent.position);
+                        *self.alive.get_mut(&opponent.faction).unwrap() -= 1;
+                        self.board.occupants[index] = EMPTY;
+                        self.version += 1;
+                    }
+                }
+
+                let unit = &self.units[i];
+                let to = Some(unit.position).filter(|&position| position != from);
+                self.record_turn(
+                    round + 1,
+                    Turn { unit: unit.id, faction: unit.faction, from, to, attack },
+                );
+
+                if protected_unit_died {
+                    self.record_end_of_round(round + 1, false);
+                    return Err(GameError::ProtectedUnitDied);
                 }
             }
+
+            self.record_end_of_round(round + 1, true);
         }
 
         Err(GameError::NoSolution)
     }
 }
 
+/// Plays a game until a single faction remains.
+pub fn simulate(input: &str, rules: &CombatRules) -> Summary {
+    Grid::from_input(input, rules).play_game(None).unwrap()
+}
+
+/// Plays a game until a single faction remains, recording every turn.
+pub fn replay(input: &str, rules: &CombatRules) -> CombatLog {
+    let mut grid = Grid::from_input(input, rules).with_log();
+    let _ = grid.play_game(None);
+    grid.log.unwrap()
+}
+
 pub fn part1(input: &str) -> isize {
-    Grid::from_input(input, 3).play_game(false).unwrap()
+    simulate(input, &CombatRules::default()).outcome()
+}
+
+/// How a full game played with some attack power for a faction ended.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct PowerOutcome {
+    pub attack_power: isize,
+    /// The number of units the faction lost.
+    pub deaths: usize,
+    pub completed_rounds: isize,
+    pub winner: Option<char>,
+    pub outcome: isize,
+}
+
+/// Plays a full game for every attack power of `faction` in `powers`, for analysis.
+pub fn power_table(
+    input: &str,
+    rules: &CombatRules,
+    faction: char,
+    powers: impl IntoIterator<Item = isize>,
+) -> Vec<PowerOutcome> {
+    powers
+        .into_iter()
+        .map(|attack_power| {
+            let summary = simulate(input, &rules.clone().with_attack_power(faction, attack_power));
+            PowerOutcome {
+                attack_power,
+                deaths: summary.casualties.get(&faction).cloned().unwrap_or(0),
+                completed_rounds: summary.completed_rounds,
+                winner: summary.winner,
+                outcome: summary.outcome(),
+            }
+        })
+        .collect()
+}
+
+/// How far below the power found by the binary search to look for an even lower one.
+const VERIFICATION_WINDOW: isize = 8;
+
+/// Finds the lowest attack power above the one in `rules` with which `faction` wins without
+/// losing a single unit, along with the resulting game.
+///
+/// The power is first doubled until such a game is found, then narrowed down by a binary search.
+/// Since winning isn't necessarily monotone in the attack power, the powers just below the one
+/// found are then checked one by one. Every game is aborted as soon as a unit of the faction dies.
+/// A power that kills any unit in a single hit makes every higher power play out the same, so the
+/// search gives up after that one.
+pub fn minimum_attack_power(
+    input: &str,
+    rules: &CombatRules,
+    faction: char,
+) -> Option<(isize, Summary)> {
+    let mut games = BTreeMap::new();
+    let mut play = |attack_power: isize| {
+        games
+            .entry(attack_power)
+            .or_insert_with(|| {
+                let rules = rules.clone().with_attack_power(faction, attack_power);
+                Grid::from_input(input, &rules).play_game(Some(faction)).ok()
+            })
+            .is_some()
+    };
+
+    let base = rules.faction(faction).attack_power;
+    let max_health_points = rules
+        .factions
+        .values()
+        .chain(Some(&rules.default))
+        .map(|rules| rules.health_points)
+        .max()
+        .unwrap_or(0);
+    let limit = max_health_points.saturating_sub(rules.death_threshold).max(base + 1);
+
+    let mut low = base;
+    let mut high = base + 1;
+    while !play(high) {
+        if high >= limit {
+            return None;
+        }
+        low = high;
+        high = (base + 2 * (high - base)).min(limit);
+    }
+
+    while high - low > 1 {
+        let middle = low + (high - low) / 2;
+        if play(middle) {
+            high = middle;
+        } else {
+            low = middle;
+        }
+    }
+
+    let attack_power =
+        ((high - VERIFICATION_WINDOW).max(base + 1)..high).find(|&p| play(p)).unwrap_or(high);
+
+    games.remove(&attack_power).unwrap().map(|summary| (attack_power, summary))
 }
 
 pub fn part2(input: &str) -> isize {
-    (4..)
-        .find_map(|elf_attack_power| Grid::from_input(input, elf_attack_power).play_game(true).ok())
-        .unwrap()
+    let (_, summary) = minimum_attack_power(input, &CombatRules::default(), ELF).unwrap();
+    summary.outcome()
 }
 
 #[cfg(test)]
@@ -298,6 +889,7 @@ mod tests {
     use super::*;
 
     const INPUT: &str = include_str!("input");
+    const TEST_INPUT_0: &str = include_str!("test_input_0");
     const TEST_INPUT_1: &str = include_str!("test_input_1");
     const TEST_INPUT_2: &str = include_str!("test_input_2");
     const TEST_INPUT_3: &str = include_str!("test_input_3");
@@ -305,6 +897,178 @@ mod tests {
     const TEST_INPUT_5: &str = include_str!("test_input_5");
     const TEST_INPUT_6: &str = include_str!("test_input_6");
 
+    #[test]
+    fn next_step_breaks_ties_in_reading_order() {
+        let mut grid = Grid::from_input(
+            "#######\n#.E...#\n#.....#\n#...G.#\n#######",
+            &CombatRules::default(),
+        );
+        grid.place_units();
+
+        assert_eq!(
+            grid.next_step(0).map(|index| grid.board.position(index)),
+            Some(Position { y: 1, x: 3 })
+        );
+
+        let mut grid = Grid::from_input(
+            "#######\n#E..G.#\n#...#.#\n#.G.#G#\n#######",
+            &CombatRules::default(),
+        );
+        grid.place_units();
+
+        assert_eq!(
+            grid.next_step(0).map(|index| grid.board.position(index)),
+            Some(Position { y: 1, x: 2 })
+        );
+    }
+
+    #[test]
+    fn larger_arenas_work() {
+        // An arena with scattered pillars, elves on the left and goblins on the right
+        let size = 40;
+        let mut input = String::new();
+        for y in 0..size {
+            for x in 0..size {
+                input.push(match (x, y) {
+                    (0, _) | (_, 0) => '#',
+                    (x, y) if x == size - 1 || y == size - 1 => '#',
+                    (x, y) if (x * 7 + y * 13) % 23 == 0 => '#',
+                    (x, y) if x < 4 && y % 3 == 1 => 'E',
+                    (x, y) if x > size - 5 && y % 4 == 2 => 'G',
+                    _ => '.',
+                });
+            }
+            input.push('\n');
+        }
+
+        let rules = CombatRules::default();
+        let summary = simulate(&input, &rules);
+
+        assert_eq!(summary.winner, Some(ELF));
+        assert_eq!(summary.casualties, vec![(ELF, 11), (GOBLIN, 29)].into_iter().collect());
+        assert_eq!(summary.outcome(), 104 * 3623);
+
+        let (power, summary) = minimum_attack_power(&input, &rules, ELF).unwrap();
+
+        assert_eq!(power, 7);
+        assert_eq!(summary.survivors, 39);
+        assert_eq!(summary.outcome(), 54 * 5973);
+    }
+
+    #[test]
+    fn replay_matches_worked_examples() {
+        let log = replay(TEST_INPUT_0, &CombatRules::default());
+
+        assert_eq!(
+            log.rounds[2].snapshot.map.join("\n"),
+            "#########\n#.......#\n#..GGG..#\n#..GEG..#\n#G..G...#\n#......G#\n#.......#\n#.......#\n#########"
+        );
+
+        let log = replay(TEST_INPUT_1, &CombatRules::default());
+
+        assert_eq!(
+            log.initial.to_string(),
+            "#######\n#.G...#   G(200)\n#...EG#   E(200), G(200)\n#.#.#G#   G(200)\n#..G#E#   G(200), E(200)\n#.....#\n#######\n"
+        );
+        assert_eq!(
+            log.rounds[0].snapshot.to_string(),
+            "#######\n#..G..#   G(200)\n#...EG#   E(197), G(197)\n#.#G#G#   G(200), G(197)\n#...#E#   E(197)\n#.....#\n#######\n"
+        );
+        assert_eq!(
+            log.rounds[46].snapshot.to_string(),
+            "#######\n#G....#   G(200)\n#.G...#   G(131)\n#.#.#G#   G(59)\n#...#.#\n#....G#   G(200)\n#######\n"
+        );
+        assert_eq!(log.rounds.len(), 47);
+        assert!(log.rounds[46].completed);
+        assert_eq!(log.outcome, Some(27730));
+
+        let text = log.to_string();
+        assert!(text.starts_with("Initially:\n#######\n"));
+        assert!(text.contains("\nAfter 47 rounds:\n#######\n#G....#   G(200)\n"));
+        assert!(text.ends_with("Outcome: 27730\n"));
+    }
+
+    #[test]
+    fn replay_exports_json() {
+        let json = replay(TEST_INPUT_1, &CombatRules::default()).to_json();
+
+        assert!(json.starts_with("{\"initial\":{\"map\":[\"#######\",\"#.G...#\","));
+        assert!(json.contains(
+            "{\"unit\":0,\"faction\":\"G\",\"from\":{\"x\":2,\"y\":1},\"to\":{\"x\":3,\"y\":1},\"attack\":null}"
+        ));
+        assert!(json.ends_with("\"outcome\":27730}"));
+    }
+
+    #[test]
+    fn simulate_summarizes_the_game() {
+        let summary = simulate(TEST_INPUT_1, &CombatRules::default());
+
+        assert_eq!(summary.winner, Some(GOBLIN));
+        assert_eq!(summary.completed_rounds, 47);
+        assert_eq!(summary.remaining_health_points, 590);
+        assert_eq!(summary.survivors, 4);
+        assert_eq!(summary.casualties, vec![(ELF, 2), (GOBLIN, 0)].into_iter().collect());
+        assert_eq!(summary.outcome(), 27730);
+    }
+
+    #[test]
+    fn custom_rules_work() {
+        let duel = "####\n#EG#\n####";
+        let rules = CombatRules::default().with_attack_power(ELF, 100);
+
+        assert_eq!(simulate(duel, &rules).outcome(), 2 * 197);
+        assert_eq!(
+            simulate(duel, &CombatRules { death_threshold: -1, ..rules }).outcome(),
+            3 * 194
+        );
+
+        let melee = "#######\n#E...D#\n#..G..#\n#######";
+        let rules = CombatRules::default()
+            .with_faction('D', FactionRules { health_points: 1000, attack_power: 50 });
+        let summary = simulate(melee, &rules);
+
+        assert_eq!(summary.winner, Some('D'));
+        assert_eq!(summary.survivors, 1);
+        assert_eq!(summary.casualties, vec![('D', 0), (ELF, 1), (GOBLIN, 1)].into_iter().collect());
+    }
+
+    #[test]
+    fn minimum_attack_power_works() {
+        let rules = CombatRules::default();
+
+        let powers = [TEST_INPUT_1, TEST_INPUT_3, TEST_INPUT_4, TEST_INPUT_5, TEST_INPUT_6]
+            .iter()
+            .map(|input| minimum_attack_power(input, &rules, ELF).map(|(power, _)| power))
+            .collect::<Vec<_>>();
+
+        assert_eq!(powers, vec![Some(15), Some(4), Some(15), Some(12), Some(34)]);
+
+        let table = power_table(TEST_INPUT_1, &rules, ELF, 14..=15);
+
+        assert_eq!(table[0].attack_power, 14);
+        assert!(table[0].deaths > 0);
+        assert_eq!(
+            table[1],
+            PowerOutcome {
+                attack_power: 15,
+                deaths: 0,
+                completed_rounds: 29,
+                winner: Some(ELF),
+                outcome: 4988
+            }
+        );
+
+        // The goblin kills the elf in a single hit, so the elf has to do the same, which takes the
+        // goblin's hit points, minus the death threshold
+        let duel = "####\n#EG#\n####";
+        let rules = CombatRules::default().with_attack_power(GOBLIN, 200);
+
+        assert_eq!(minimum_attack_power(duel, &rules, ELF).map(|(power, _)| power), Some(200));
+
+        let rules = CombatRules { death_threshold: 1, ..rules };
+        assert_eq!(minimum_attack_power(duel, &rules, ELF).map(|(power, _)| power), Some(199));
+    }
+
     #[test]
     fn part1_works() {
         assert_eq!(part1(TEST_INPUT_1), 27730);
@@ -319,7 +1083,7 @@ mod tests {
     #[test]
     fn part2_works() {
         assert_eq!(part2(TEST_INPUT_1), 4988);
-        //assert_eq!(part2(TEST_INPUT_3), 31284);
+        assert_eq!(part2(TEST_INPUT_3), 31284);
         assert_eq!(part2(TEST_INPUT_4), 3478);
         assert_eq!(part2(TEST_INPUT_5), 6474);
         assert_eq!(part2(TEST_INPUT_6), 1140);
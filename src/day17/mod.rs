@@ -1,14 +1,18 @@
+use core::ops::RangeInclusive;
 use itertools::Itertools;
 use lazy_static::lazy_static;
 use regex::Regex;
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeSet, HashMap, HashSet};
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
 pub struct Position {
-    y: isize,
-    x: isize,
+    pub y: isize,
+    pub x: isize,
 }
 
+/// The spring of the puzzle.
+pub const SPRING: Position = Position { x: 500, y: 0 };
+
 impl Position {
     fn moved_by(self, direction: Direction) -> Position {
         let Position { x, y } = self;
@@ -31,12 +35,35 @@ pub enum Direction {
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 enum Tile {
+    Sand,
     Clay,
     FlowingWater,
     SettledWater,
 }
 
-fn parse(input: &str) -> HashMap<Position, Tile> {
+impl Tile {
+    fn is_water(self) -> bool {
+        match self {
+            Tile::FlowingWater | Tile::SettledWater => true,
+            Tile::Sand | Tile::Clay => false,
+        }
+    }
+}
+
+/// A vein of clay from the scan, which is either vertical or horizontal.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Vein {
+    pub x: RangeInclusive<isize>,
+    pub y: RangeInclusive<isize>,
+}
+
+impl Vein {
+    fn positions(&self) -> impl Iterator<Item = Position> {
+        self.x.clone().cartesian_product(self.y.clone()).map(|(x, y)| Position { x, y })
+    }
+}
+
+fn parse_veins(input: &str) -> Vec<Vein> {
     lazy_static! {
         static ref PATTERN: Regex = Regex::new(r"([xy])=(\d+), [xy]=(\d+)..(\d+)").unwrap();
     }
@@ -49,147 +76,372 @@ fn parse(input: &str) -> HashMap<Position, Tile> {
             let range = caps.get(3)?.as_str().parse().ok()?..=caps.get(4)?.as_str().parse().ok()?;
 
             Some(match symbol {
-                "x" => ((index..=index), range),
-                "y" => (range, (index..=index)),
+                "x" => Vein { x: index..=index, y: range },
+                "y" => Vein { x: range, y: index..=index },
                 _ => panic!("unexpected symbol!"),
             })
         })
-        .flat_map(|(x_range, y_range)| {
-            x_range.cartesian_product(y_range).map(|(x, y)| (Position { x, y }, Tile::Clay))
-        })
         .collect()
 }
 
-#[allow(dead_code)]
-fn compute_bounds(positions: impl Iterator<Item = Position>) -> Option<(Position, Position)> {
-    use core::cmp::{max, min};
-    positions.fold(None, |current_bounds, current_position| {
-        if let Some((min_position, max_position)) = current_bounds {
-            Some((
-                Position {
-                    x: min(min_position.x, current_position.x),
-                    y: min(min_position.y, current_position.y),
-                },
-                Position {
-                    x: max(max_position.x, current_position.x),
-                    y: max(max_position.y, current_position.y),
-                },
-            ))
-        } else {
-            Some((current_position, current_position))
-        }
-    })
+/// A dense grid covering the clay and the springs, with an extra column on either side for the
+/// water flowing over the edges. Everything outside of it is sand.
+struct Ground {
+    min_x: isize,
+    min_y: isize,
+    width: usize,
+    height: usize,
+    tiles: Vec<Tile>,
 }
 
-#[allow(dead_code)]
-fn draw(map: &HashMap<Position, Tile>) {
-    use core::cmp::{max, min};
+impl Ground {
+    fn new(veins: &[Vein], springs: &[Position]) -> Ground {
+        let (min_x, max_x) = veins
+            .iter()
+            .flat_map(|vein| vec![*vein.x.start(), *vein.x.end()])
+            .chain(springs.iter().map(|position| position.x))
+            .minmax()
+            .into_option()
+            .unwrap();
+        // Water below the deepest clay would flow forever, so the ground ends there
+        let min_y = veins
+            .iter()
+            .map(|vein| *vein.y.start())
+            .chain(springs.iter().map(|position| position.y))
+            .min()
+            .unwrap();
+        let max_y = veins.iter().map(|vein| *vein.y.end()).max().unwrap();
+
+        let mut ground = Ground {
+            min_x: min_x - 1,
+            min_y,
+            width: (max_x - min_x + 3) as usize,
+            height: (max_y - min_y + 1) as usize,
+            tiles: Vec::new(),
+        };
+
+        ground.tiles = vec![Tile::Sand; ground.width * ground.height];
+        for position in veins.iter().flat_map(|vein| vein.positions()) {
+            ground.set(position, Tile::Clay);
+        }
+
+        ground
+    }
+
+    fn index(&self, position: Position) -> Option<usize> {
+        let x = position.x - self.min_x;
+        let y = position.y - self.min_y;
 
-    let bounds = map.keys().fold(None, |current_bounds, position| {
-        let Position { x, y } = *position;
-        if let Some((min_x, min_y, max_x, max_y)) = current_bounds {
-            Some((min(min_x, x), min(min_y, y), max(max_x, x), max(max_y, y)))
+        if 0 <= x && (x as usize) < self.width && 0 <= y && (y as usize) < self.height {
+            Some(y as usize * self.width + x as usize)
         } else {
-            Some((x, y, x, y))
+            None
         }
-    });
-
-    if let Some((min_x, min_y, max_x, max_y)) = bounds {
-        for y in min_y - 1..=max_y + 1 {
-            let line = (min_x - 1..=max_x + 1)
-                .map(move |x| {
-                    let position = Position { x, y };
-                    match map.get(&position) {
-                        Some(Tile::FlowingWater) => '|',
-                        Some(Tile::SettledWater) => '~',
-                        Some(Tile::Clay) => '#',
-                        None => '.',
-                    }
-                })
-                .collect::<String>();
+    }
 
-            println!("{}", line);
+    fn position(&self, index: usize) -> Position {
+        Position {
+            x: (index % self.width) as isize + self.min_x,
+            y: (index / self.width) as isize + self.min_y,
         }
     }
-}
 
-fn solve(map: &mut HashMap<Position, Tile>) {
-    let (min_y, max_y) = map.keys().map(|position| position.y).minmax().into_option().unwrap();
+    fn get(&self, position: Position) -> Tile {
+        self.index(position).map_or(Tile::Sand, |index| self.tiles[index])
+    }
+
+    fn set(&mut self, position: Position, tile: Tile) {
+        let index = self.index(position).unwrap();
+        self.tiles[index] = tile;
+    }
+
+    fn tiles(&self) -> impl Iterator<Item = (Position, Tile)> + '_ {
+        self.tiles.iter().enumerate().map(move |(index, &tile)| (self.position(index), tile))
+    }
+
+    /// Lets the water from the springs flow down to the lowest row of the ground.
+    ///
+    /// Water falls until it lands on clay or settled water, and then spreads over the whole row
+    /// at once until it either hits clay on both sides, settling the entire span, or runs over an
+    /// edge, from where it falls again. Every tile the water falls through is revisited once the
+    /// water below it has come to rest, since the row it spreads on may have filled up by then.
+    fn fill(&mut self, springs: &[Position]) {
+        let width = self.width;
+        let mut stack = springs.iter().filter_map(|&spring| self.index(spring)).collect::<Vec<_>>();
 
-    let mut stack = VecDeque::new();
-    let starting_position = Position { x: 500, y: 0 };
-    stack.push_back(starting_position);
+        while let Some(index) = stack.pop() {
+            self.tiles[index] = Tile::FlowingWater;
+            let down = index + width;
 
-    while let Some(position) = stack.pop_back() {
-        map.insert(position, Tile::FlowingWater);
-        let down_position = position.moved_by(Direction::Down);
+            match self.tiles.get(down) {
+                None | Some(Tile::FlowingWater) => {}
 
-        match map.get(&down_position) {
-            None => {
-                if position.y < max_y {
-                    stack.push_back(position);
-                    stack.push_back(down_position);
+                Some(Tile::Sand) => {
+                    stack.push(index);
+                    stack.push(down);
                 }
-            }
 
-            Some(Tile::FlowingWater) => {}
+                Some(Tile::Clay) | Some(Tile::SettledWater) => {
+                    let row_start = index - index % width;
+                    let row_end = row_start + width;
 
-            Some(Tile::Clay) | Some(Tile::SettledWater) => {
-                let search = |direction| {
-                    let mut position = position;
-                    loop {
-                        let next_position = position.moved_by(direction);
-                        let next_tile = map.get(&next_position);
-                        let down_tile = map.get(&position.moved_by(Direction::Down));
+                    let search = |step: fn(usize) -> usize| {
+                        let mut index = index;
+                        loop {
+                            let next = step(index);
+                            let in_row = next >= row_start && next < row_end;
+                            let next_tile = if in_row { self.tiles[next] } else { Tile::Sand };
+                            let down_tile = self.tiles[index + width];
 
-                        if next_tile == Some(&Tile::Clay) {
-                            break (position.x, true);
-                        } else if down_tile == None || down_tile == Some(&Tile::FlowingWater) {
-                            break (position.x, false);
+                            if next_tile == Tile::Clay {
+                                break (index, true);
+                            } else if !in_row
+                                || down_tile == Tile::Sand
+                                || down_tile == Tile::FlowingWater
+                            {
+                                break (index, false);
+                            }
+
+                            index = next;
                         }
+                    };
 
-                        position = next_position;
-                    }
-                };
+                    let (left, hit_left_wall) = search(|index| index.wrapping_sub(1));
+                    let (right, hit_right_wall) = search(|index| index + 1);
 
-                let y = position.y;
-                let (left_x, hit_left_wall) = search(Direction::Left);
-                let (right_x, hit_right_wall) = search(Direction::Right);
+                    if hit_left_wall && hit_right_wall {
+                        self.tiles[left..=right]
+                            .iter_mut()
+                            .for_each(|tile| *tile = Tile::SettledWater);
+                    } else {
+                        self.tiles[left..=right]
+                            .iter_mut()
+                            .for_each(|tile| *tile = Tile::FlowingWater);
 
-                if hit_left_wall && hit_right_wall {
-                    for x in left_x..=right_x {
-                        map.insert(Position { x, y }, Tile::SettledWater);
-                    }
-                } else {
-                    for x in left_x..=right_x {
-                        map.insert(Position { x, y }, Tile::FlowingWater);
-                    }
+                        if !hit_left_wall {
+                            stack.push(left);
+                        }
 
-                    if !hit_left_wall {
-                        stack.push_back(Position { x: left_x, y });
+                        if !hit_right_wall {
+                            stack.push(right);
+                        }
                     }
+                }
+            }
+        }
+    }
+}
+
+#[allow(dead_code)]
+fn draw(ground: &Ground) {
+    for y in 0..ground.height {
+        let line = ground.tiles[y * ground.width..(y + 1) * ground.width]
+            .iter()
+            .map(|tile| match tile {
+                Tile::FlowingWater => '|',
+                Tile::SettledWater => '~',
+                Tile::Clay => '#',
+                Tile::Sand => '.',
+            })
+            .collect::<String>();
+
+        println!("{}", line);
+    }
+}
+
+/// A connected pool of settled water.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Basin {
+    /// The topmost, then leftmost tile of the basin.
+    pub origin: Position,
+    pub settled_volume: usize,
+    /// Where the water flowing over the basin falls off its edges.
+    pub overflow_points: Vec<Position>,
+    /// The veins of clay holding the basin, as indices into `Reservoir::veins`.
+    pub veins: Vec<usize>,
+}
+
+/// The ground once the water from every spring has come to rest. Only the rows between the
+/// topmost and bottommost clay count as the scan.
+pub struct Reservoir {
+    ground: Ground,
+    veins: Vec<Vein>,
+    springs: Vec<Position>,
+    min_y: isize,
+}
+
+impl Reservoir {
+    pub fn new(input: &str, springs: &[Position]) -> Reservoir {
+        let veins = parse_veins(input);
+        let min_y = veins.iter().map(|vein| *vein.y.start()).min().unwrap();
+        let mut ground = Ground::new(&veins, springs);
+        ground.fill(springs);
+        Reservoir { ground, veins, springs: springs.to_vec(), min_y }
+    }
+
+    fn scanned_tiles(&self) -> impl Iterator<Item = (Position, Tile)> + '_ {
+        let min_y = self.min_y;
+        self.ground.tiles().filter(move |(position, _)| position.y >= min_y)
+    }
+
+    pub fn veins(&self) -> &[Vein] {
+        &self.veins
+    }
+
+    pub fn springs(&self) -> &[Position] {
+        &self.springs
+    }
+
+    /// The number of tiles with water, flowing or settled.
+    pub fn water_volume(&self) -> usize {
+        self.scanned_tiles().filter(|&(_, tile)| tile.is_water()).count()
+    }
+
+    pub fn settled_volume(&self) -> usize {
+        self.scanned_tiles().filter(|&(_, tile)| tile == Tile::SettledWater).count()
+    }
+
+    fn is_water(&self, position: Position) -> bool {
+        self.ground.get(position).is_water()
+    }
+
+    fn is_supporting(&self, position: Position) -> bool {
+        match self.ground.get(position) {
+            Tile::Clay | Tile::SettledWater => true,
+            Tile::Sand | Tile::FlowingWater => false,
+        }
+    }
+
+    /// The pools of settled water, in reading order.
+    pub fn basins(&self) -> Vec<Basin> {
+        let mut vein_at = HashMap::new();
+        for (index, vein) in self.veins.iter().enumerate() {
+            for position in vein.positions() {
+                vein_at.entry(position).or_insert(index);
+            }
+        }
 
-                    if !hit_right_wall {
-                        stack.push_back(Position { x: right_x, y });
+        let mut settled = self
+            .scanned_tiles()
+            .filter(|&(_, tile)| tile == Tile::SettledWater)
+            .map(|(position, _)| position)
+            .collect::<BTreeSet<_>>();
+
+        let mut basins = Vec::new();
+
+        while let Some(&origin) = settled.iter().next() {
+            let mut tiles = vec![origin];
+            let mut queue = vec![origin];
+            settled.remove(&origin);
+
+            while let Some(position) = queue.pop() {
+                for &direction in
+                    &[Direction::Up, Direction::Down, Direction::Left, Direction::Right]
+                {
+                    let neighbor = position.moved_by(direction);
+                    if settled.remove(&neighbor) {
+                        tiles.push(neighbor);
+                        queue.push(neighbor);
                     }
                 }
             }
+
+            let veins = tiles
+                .iter()
+                .flat_map(|&position| {
+                    [Direction::Down, Direction::Left, Direction::Right]
+                        .iter()
+                        .filter_map(|&direction| vein_at.get(&position.moved_by(direction)))
+                        .cloned()
+                        .collect::<Vec<_>>()
+                })
+                .collect::<BTreeSet<_>>();
+
+            basins.push(Basin {
+                origin,
+                settled_volume: tiles.len(),
+                overflow_points: self.overflow_points(&tiles),
+                veins: veins.into_iter().collect(),
+            });
+        }
+
+        basins
+    }
+
+    /// Follows the flowing water on top of a basin sideways, to where it falls off.
+    fn overflow_points(&self, tiles: &[Position]) -> Vec<Position> {
+        let mut visited = HashSet::new();
+        let mut queue = tiles
+            .iter()
+            .map(|position| position.moved_by(Direction::Up))
+            .filter(|&position| self.ground.get(position) == Tile::FlowingWater)
+            .collect::<Vec<_>>();
+        let mut overflow_points = BTreeSet::new();
+
+        while let Some(position) = queue.pop() {
+            if !visited.insert(position) {
+                continue;
+            }
+
+            if !self.is_supporting(position.moved_by(Direction::Down)) {
+                overflow_points.insert(position);
+                continue;
+            }
+
+            for &direction in &[Direction::Left, Direction::Right] {
+                let neighbor = position.moved_by(direction);
+                if self.ground.get(neighbor) == Tile::FlowingWater {
+                    queue.push(neighbor);
+                }
+            }
         }
+
+        overflow_points.into_iter().collect()
     }
 
-    map.retain(|position, _| min_y <= position.y && position.y <= max_y);
+    /// The scanned tiles that the water of a spring reaches, by following the water downwards
+    /// and, on top of clay or settled water, sideways. Reaching a basin reaches all of it.
+    pub fn reached_by(&self, spring: Position) -> HashSet<Position> {
+        let mut reached = HashSet::new();
+        let mut queue = vec![spring];
+
+        while let Some(position) = queue.pop() {
+            if !self.is_water(position) || !reached.insert(position) {
+                continue;
+            }
+
+            let down = position.moved_by(Direction::Down);
+            let mut directions = vec![Direction::Left, Direction::Right];
+
+            if self.ground.get(position) == Tile::SettledWater {
+                directions.extend(&[Direction::Up, Direction::Down]);
+            } else if !self.is_supporting(down) {
+                directions = vec![Direction::Down];
+            } else if self.ground.get(down) == Tile::SettledWater {
+                directions.push(Direction::Down);
+            }
+
+            for direction in directions {
+                let neighbor = position.moved_by(direction);
+                if self.ground.get(position) != Tile::SettledWater
+                    || self.ground.get(neighbor) == Tile::SettledWater
+                {
+                    queue.push(neighbor);
+                }
+            }
+        }
+
+        reached.retain(|position| position.y >= self.min_y);
+        reached
+    }
 }
 
 pub fn part1(input: &str) -> usize {
-    let mut map = parse(input);
-    solve(&mut map);
-    map.values().filter(|tile| **tile == Tile::FlowingWater || **tile == Tile::SettledWater).count()
+    Reservoir::new(input, &[SPRING]).water_volume()
 }
 
 pub fn part2(input: &str) -> usize {
-    let mut map = parse(input);
-    solve(&mut map);
-    map.values().filter(|tile| **tile == Tile::SettledWater).count()
+    Reservoir::new(input, &[SPRING]).settled_volume()
 }
 
 #[cfg(test)]
@@ -199,6 +451,55 @@ mod tests {
     const TEST_INPUT: &str = include_str!("test_input");
     const INPUT: &str = include_str!("input");
 
+    #[test]
+    fn basins_work() {
+        let reservoir = Reservoir::new(TEST_INPUT, &[SPRING]);
+
+        assert_eq!(
+            reservoir.basins(),
+            vec![
+                Basin {
+                    origin: Position { x: 499, y: 3 },
+                    settled_volume: 14,
+                    overflow_points: vec![Position { x: 502, y: 2 }],
+                    veins: vec![0, 1, 2, 3],
+                },
+                Basin {
+                    origin: Position { x: 499, y: 10 },
+                    settled_volume: 15,
+                    overflow_points: vec![Position { x: 497, y: 9 }, Position { x: 505, y: 9 }],
+                    veins: vec![5, 6, 7],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn multiple_springs_work() {
+        let other_spring = Position { x: 506, y: 0 };
+        let reservoir = Reservoir::new(TEST_INPUT, &[SPRING, other_spring]);
+
+        assert_eq!(reservoir.water_volume(), 78);
+        assert_eq!(reservoir.settled_volume(), 29);
+
+        let reached = reservoir.reached_by(SPRING);
+        let other_reached = reservoir.reached_by(other_spring);
+
+        assert_eq!(reached.len(), 57);
+        assert_eq!(other_reached.len(), 26);
+        assert_eq!(reached.intersection(&other_reached).count(), 5);
+        assert!(other_reached.contains(&Position { x: 507, y: 1 }));
+    }
+
+    #[test]
+    fn large_scans_work() {
+        let input = "x=1000, y=10..2000\nx=3000, y=10..2000\ny=2000, x=1000..3000\n";
+        let reservoir = Reservoir::new(input, &[Position { x: 2000, y: 0 }]);
+
+        assert_eq!(reservoir.settled_volume(), 1999 * 1990);
+        assert_eq!(reservoir.water_volume(), 1999 * 1990 + 2 * 1991);
+    }
+
     #[test]
     fn part1_works() {
         assert_eq!(part1(TEST_INPUT), 57);
@@ -1,6 +1,4 @@
-use core::cmp::min;
-use std::collections::{hash_map::DefaultHasher, HashMap};
-use std::hash::{Hash, Hasher};
+use crate::automaton::{Automaton, Cycle};
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 enum Acre {
@@ -9,28 +7,39 @@ enum Acre {
     Lumberyard,
 }
 
-fn parse(input: &str) -> (Vec<Acre>, usize) {
-    let acres = input
-        .chars()
-        .filter_map(|c| match c {
-            '.' => Some(Acre::OpenGround),
-            '|' => Some(Acre::Trees),
-            '#' => Some(Acre::Lumberyard),
-            _ => None,
+impl Default for Acre {
+    fn default() -> Acre {
+        Acre::OpenGround
+    }
+}
+
+fn parse(input: &str) -> (Vec<Acre>, usize, usize) {
+    let lines = input.lines().map(str::trim).filter(|line| !line.is_empty()).collect::<Vec<_>>();
+    let width = lines.first().map_or(0, |line| line.len());
+
+    let acres = lines
+        .iter()
+        .flat_map(|line| {
+            assert_eq!(line.len(), width, "All rows must be equally wide");
+            line.chars()
+        })
+        .map(|c| match c {
+            '.' => Acre::OpenGround,
+            '|' => Acre::Trees,
+            '#' => Acre::Lumberyard,
+            _ => panic!("Invalid acre: {}", c),
         })
         .collect::<Vec<_>>();
 
-    // Integer square root...
-    let size = (1..acres.len()).find(|i| i * i == acres.len()).expect("Invalid size");
-
-    (acres, size)
+    (acres, width, lines.len())
 }
 
 #[allow(dead_code)]
-fn draw(acres: &[Acre], size: usize) {
-    for y in 0..size {
-        let line = (0..size)
-            .map(|x| match acres[x + size * y] {
+fn draw(acres: &[Acre], width: usize) {
+    for row in acres.chunks(width) {
+        let line = row
+            .iter()
+            .map(|acre| match acre {
                 Acre::OpenGround => '.',
                 Acre::Trees => '|',
                 Acre::Lumberyard => '#',
@@ -40,84 +49,39 @@ fn draw(acres: &[Acre], size: usize) {
     }
 }
 
+fn next_acre(acre: Acre, neighbors: &[Acre]) -> Acre {
+    let trees_count = neighbors.iter().filter(|&&acre| acre == Acre::Trees).count();
+    let lumberyard_count = neighbors.iter().filter(|&&acre| acre == Acre::Lumberyard).count();
+
+    match acre {
+        Acre::OpenGround if trees_count >= 3 => Acre::Trees,
+        Acre::Trees if lumberyard_count >= 3 => Acre::Lumberyard,
+        Acre::Lumberyard if trees_count == 0 || lumberyard_count == 0 => Acre::OpenGround,
+        acre => acre,
+    }
+}
+
+fn resource_value(acres: &[Acre]) -> usize {
+    let trees_count = acres.iter().filter(|&&acre| acre == Acre::Trees).count();
+    let lumberyard_count = acres.iter().filter(|&&acre| acre == Acre::Lumberyard).count();
+
+    trees_count * lumberyard_count
+}
+
+/// Finds the first state of the lumber collection area that repeats, within `limit` minutes.
+pub fn find_cycle(input: &str, limit: usize) -> Option<Cycle> {
+    let (acres, width, height) = parse(input);
+    Automaton::new(width, height, acres, next_acre).find_cycle(limit)
+}
+
 #[allow(dead_code)]
 fn solve(input: &str, iterations: usize) -> usize {
-    let (acres, size) = parse(input);
-
-    let mut index_by_hash = HashMap::<u64, usize>::new();
-    let mut answers_by_index = Vec::<usize>::new();
-
-    let mut next_acres = acres.clone();
-    let mut prev_acres = acres;
-
-    let mut i = 0;
-
-    loop {
-        for y in 0..size {
-            for x in 0..size {
-                let mut trees_count = 0;
-                let mut lumberyard_count = 0;
-
-                let mut count = |x, y| match prev_acres[x + size * y] {
-                    Acre::Trees => trees_count += 1,
-                    Acre::Lumberyard => lumberyard_count += 1,
-                    _ => {}
-                };
-
-                #[allow(clippy::needless_range_loop)]
-                for y2 in if y == 0 { 0 } else { y - 1 }..min(y + 2, size) {
-                    for x2 in if x == 0 { 0 } else { x - 1 }..min(x + 2, size) {
-                        if (x, y) != (x2, y2) {
-                            count(x2, y2);
-                        }
-                    }
-                }
-
-                next_acres[x + size * y] = match prev_acres[x + size * y] {
-                    Acre::OpenGround if trees_count >= 3 => Acre::Trees,
-                    Acre::Trees if lumberyard_count >= 3 => Acre::Lumberyard,
-                    Acre::Lumberyard if trees_count == 0 || lumberyard_count == 0 => {
-                        Acre::OpenGround
-                    }
-                    acre => acre,
-                };
-            }
-        }
-
-        let mut trees_count = 0;
-        let mut lumberyard_count = 0;
-
-        for acre in next_acres.iter() {
-            match acre {
-                Acre::Trees => trees_count += 1,
-                Acre::Lumberyard => lumberyard_count += 1,
-                _ => {}
-            }
-        }
-
-        let answer = trees_count * lumberyard_count;
-
-        if i + 1 == iterations {
-            return answer;
-        }
-
-        let hash = {
-            let mut hasher = DefaultHasher::new();
-            Hash::hash_slice(&next_acres, &mut hasher);
-            hasher.finish()
-        };
-
-        if let Some(i0) = index_by_hash.get(&hash) {
-            let repeating_slice = &answers_by_index[*i0..i];
-            return repeating_slice[(iterations - i0 - 1) % repeating_slice.len()];
-        }
-
-        answers_by_index.push(answer);
-        index_by_hash.insert(hash, i);
-        prev_acres.clone_from(&next_acres);
-
-        i += 1;
-    }
+    let (acres, width, height) = parse(input);
+
+    let mut automaton = Automaton::new(width, height, acres, next_acre);
+    automaton.advance(iterations);
+
+    resource_value(automaton.cells())
 }
 
 #[cfg(test)]
@@ -127,6 +91,21 @@ mod tests {
     const TEST_INPUT: &str = include_str!("test_input");
     const INPUT: &str = include_str!("input");
 
+    #[test]
+    fn find_cycle_works() {
+        assert_eq!(find_cycle(INPUT, 1000), Some(Cycle { start: 513, length: 28 }));
+        assert_eq!(find_cycle(INPUT, 100), None);
+    }
+
+    #[test]
+    fn rectangular_fields_work() {
+        let input = "|||#\n|#..\n.||.\n";
+
+        assert_eq!(solve(input, 0), 6 * 2);
+        // Both lumberyards are cut down, and a single tree grows
+        assert_eq!(solve(input, 1), 0);
+    }
+
     #[test]
     fn part1_works() {
         assert_eq!(solve(TEST_INPUT, 10), 1147);
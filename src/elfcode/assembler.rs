@@ -0,0 +1,377 @@
+use super::{Instruction, Opcode};
+use core::fmt;
+use core::str::FromStr;
+use std::collections::HashMap;
+
+const REGISTER_COUNT: usize = 6;
+
+/// An assembled Elfcode program, in the canonical form used by the puzzle inputs.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Program {
+    pub instruction_pointer_index: usize,
+    pub instructions: Vec<Instruction>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum AssembleErrorKind {
+    UnknownOpcode(String),
+    WrongOperandCount(usize),
+    InvalidOperand(String),
+    InvalidRegister(usize),
+    InvalidName(String),
+    UnknownLabel(String),
+    UnknownDirective(String),
+    DuplicateLabel(String),
+    DuplicateAlias(String),
+    DuplicateInstructionPointer,
+    MissingInstructionPointer,
+}
+
+/// An error found while assembling, with the (1-based) line it was found on.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AssembleError {
+    pub line: usize,
+    pub kind: AssembleErrorKind,
+}
+
+impl fmt::Display for AssembleErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssembleErrorKind::UnknownOpcode(opcode) => write!(f, "unknown opcode `{}`", opcode),
+            AssembleErrorKind::WrongOperandCount(count) => {
+                write!(f, "expected 3 operands, found {}", count)
+            }
+            AssembleErrorKind::InvalidOperand(operand) => {
+                write!(f, "invalid operand `{}`", operand)
+            }
+            AssembleErrorKind::InvalidRegister(register) => {
+                write!(f, "register {} is out of range 0..{}", register, REGISTER_COUNT)
+            }
+            AssembleErrorKind::InvalidName(name) => write!(f, "invalid name `{}`", name),
+            AssembleErrorKind::UnknownLabel(label) => write!(f, "unknown label `{}`", label),
+            AssembleErrorKind::UnknownDirective(directive) => {
+                write!(f, "unknown directive `{}`", directive)
+            }
+            AssembleErrorKind::DuplicateLabel(label) => write!(f, "duplicate label `{}`", label),
+            AssembleErrorKind::DuplicateAlias(alias) => write!(f, "duplicate alias `{}`", alias),
+            AssembleErrorKind::DuplicateInstructionPointer => {
+                write!(f, "duplicate `#ip` directive")
+            }
+            AssembleErrorKind::MissingInstructionPointer => write!(f, "missing `#ip` directive"),
+        }
+    }
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.kind)
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "#ip {}", self.instruction_pointer_index)?;
+        for instruction in &self.instructions {
+            writeln!(f, "{}", instruction)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Program {
+    type Err = AssembleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        assemble(s)
+    }
+}
+
+struct SourceInstruction<'a> {
+    line: usize,
+    opcode: Opcode,
+    operands: Vec<&'a str>,
+}
+
+#[derive(Default)]
+struct Symbols<'a> {
+    instruction_pointer_index: Option<usize>,
+    labels: HashMap<&'a str, usize>,
+    aliases: HashMap<&'a str, usize>,
+}
+
+fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().map_or(false, |c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_register_number(s: &str) -> Option<usize> {
+    s.parse().ok().or_else(|| if s.starts_with('r') { s[1..].parse().ok() } else { None })
+}
+
+fn check_register(register: usize) -> Result<usize, AssembleErrorKind> {
+    if register < REGISTER_COUNT {
+        Ok(register)
+    } else {
+        Err(AssembleErrorKind::InvalidRegister(register))
+    }
+}
+
+impl<'a> Symbols<'a> {
+    fn register(&self, operand: &str) -> Result<usize, AssembleErrorKind> {
+        if let Some(register) = parse_register_number(operand) {
+            return check_register(register);
+        }
+
+        if operand == "ip" {
+            if let Some(register) = self.instruction_pointer_index {
+                return Ok(register);
+            }
+        }
+
+        self.aliases
+            .get(operand)
+            .cloned()
+            .ok_or_else(|| AssembleErrorKind::InvalidOperand(operand.to_string()))
+    }
+
+    /// Immediates are either plain numbers or a label with an optional `+N`/`-N` offset.
+    fn immediate(&self, operand: &str) -> Result<usize, AssembleErrorKind> {
+        if let Ok(value) = operand.parse() {
+            return Ok(value);
+        }
+
+        let invalid = || AssembleErrorKind::InvalidOperand(operand.to_string());
+
+        let (label, offset) = match operand.find(|c| c == '+' || c == '-') {
+            Some(i) => {
+                let offset: isize = operand[i + 1..].parse().map_err(|_| invalid())?;
+                (&operand[..i], if &operand[i..=i] == "-" { -offset } else { offset })
+            }
+            None => (operand, 0),
+        };
+
+        if !is_valid_name(label) {
+            return Err(invalid());
+        }
+
+        let address = *self
+            .labels
+            .get(label)
+            .ok_or_else(|| AssembleErrorKind::UnknownLabel(label.to_string()))?;
+
+        let value = address as isize + offset;
+        if value < 0 {
+            return Err(invalid());
+        }
+
+        Ok(value as usize)
+    }
+
+    fn directive(&mut self, line: &'a str) -> Result<(), AssembleErrorKind> {
+        let mut tokens = line.split_whitespace();
+        let name = tokens.next().unwrap_or_default();
+        let arguments: Vec<_> = tokens.collect();
+
+        match (name, arguments.as_slice()) {
+            ("#ip", [register]) => {
+                let register = parse_register_number(register)
+                    .ok_or_else(|| AssembleErrorKind::InvalidOperand(register.to_string()))
+                    .and_then(check_register)?;
+
+                if self.instruction_pointer_index.replace(register).is_some() {
+                    return Err(AssembleErrorKind::DuplicateInstructionPointer);
+                }
+            }
+            ("#alias", [alias, register]) => {
+                if !is_valid_name(alias) || *alias == "ip" || parse_register_number(alias).is_some()
+                {
+                    return Err(AssembleErrorKind::InvalidName(alias.to_string()));
+                }
+
+                let register = parse_register_number(register)
+                    .ok_or_else(|| AssembleErrorKind::InvalidOperand(register.to_string()))
+                    .and_then(check_register)?;
+
+                if self.aliases.insert(alias, register).is_some() {
+                    return Err(AssembleErrorKind::DuplicateAlias(alias.to_string()));
+                }
+            }
+            _ => return Err(AssembleErrorKind::UnknownDirective(line.to_string())),
+        }
+
+        Ok(())
+    }
+}
+
+/// Assembles Elfcode source into a `Program`.
+///
+/// On top of the canonical format, the source may contain:
+///
+/// - `;` comments, running to the end of the line
+/// - labels, written as `name:` before an instruction or on a line of their own, which can be used
+///   in place of any immediate operand as `name`, `name+N` or `name-N`
+/// - register aliases, declared with `#alias name N`, plus `rN` for register `N` and `ip` for the
+///   instruction pointer register
+///
+/// Keep in mind that the instruction pointer is incremented after every instruction, so jumping to
+/// `label` is written as `seti label-1 0 ip`.
+pub fn assemble(source: &str) -> Result<Program, AssembleError> {
+    let mut symbols = Symbols::default();
+    let mut source_instructions = Vec::new();
+    let mut first_line = None;
+
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let error = |kind| AssembleError { line: line_number, kind };
+        let mut line = line.split(';').next().unwrap_or_default().trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        first_line.get_or_insert(line_number);
+
+        if line.starts_with('#') {
+            symbols.directive(line).map_err(error)?;
+            continue;
+        }
+
+        while let Some(colon) = line.find(':') {
+            let label = line[..colon].trim();
+            if !is_valid_name(label) {
+                return Err(error(AssembleErrorKind::InvalidName(label.to_string())));
+            }
+            if symbols.labels.insert(label, source_instructions.len()).is_some() {
+                return Err(error(AssembleErrorKind::DuplicateLabel(label.to_string())));
+            }
+            line = line[colon + 1..].trim();
+        }
+
+        let mut tokens = line.split_whitespace();
+
+        if let Some(opcode) = tokens.next() {
+            let opcode = opcode
+                .parse()
+                .map_err(|_| error(AssembleErrorKind::UnknownOpcode(opcode.to_string())))?;
+            let operands: Vec<_> = tokens.collect();
+
+            if operands.len() != 3 {
+                return Err(error(AssembleErrorKind::WrongOperandCount(operands.len())));
+            }
+
+            source_instructions.push(SourceInstruction { line: line_number, opcode, operands });
+        }
+    }
+
+    let instruction_pointer_index = symbols.instruction_pointer_index.ok_or(AssembleError {
+        line: first_line.unwrap_or(1),
+        kind: AssembleErrorKind::MissingInstructionPointer,
+    })?;
+
+    let instructions = source_instructions
+        .iter()
+        .map(|SourceInstruction { line, opcode, operands }| {
+            let opcode = *opcode;
+            let operand = |operand: &str, is_register: bool| {
+                if is_register {
+                    symbols.register(operand)
+                } else {
+                    symbols.immediate(operand)
+                }
+            };
+
+            let instruction = || {
+                Ok(Instruction {
+                    opcode,
+                    a: operand(operands[0], opcode.reads_register_a())?,
+                    b: operand(operands[1], opcode.reads_register_b())?,
+                    c: operand(operands[2], true)?,
+                })
+            };
+
+            instruction().map_err(|kind| AssembleError { line: *line, kind })
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(Program { instruction_pointer_index, instructions })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elfcode::CPU;
+
+    const DAY19_TEST_INPUT: &str = include_str!("../day19/test_input");
+    const DAY19_INPUT: &str = include_str!("../day19/input");
+    const DAY21_INPUT: &str = include_str!("../day21/input");
+
+    #[test]
+    fn canonical_programs_round_trip() {
+        for input in &[DAY19_TEST_INPUT, DAY19_INPUT, DAY21_INPUT] {
+            let program = assemble(input).unwrap();
+            assert_eq!(program.to_string(), *input);
+            assert_eq!(program.to_string().parse(), Ok(program));
+        }
+    }
+
+    #[test]
+    fn labels_comments_and_aliases_work() {
+        let source = "
+            ; Sums the numbers 1 to 10 into register 0
+            #ip 5
+            #alias sum 0
+            #alias counter 1
+            #alias done 2
+
+                    seti 1 0 counter
+            loop:   addr sum counter sum    ; add the counter
+                    addi counter 1 counter
+                    gtri counter 10 done
+                    addr done ip ip         ; skip the jump once done
+                    seti loop-1 0 ip
+        ";
+
+        let program = assemble(source).unwrap();
+
+        assert_eq!(
+            program.to_string(),
+            "#ip 5\nseti 1 0 1\naddr 0 1 0\naddi 1 1 1\ngtri 1 10 2\naddr 2 5 5\nseti 0 0 5\n"
+        );
+
+        let mut cpu: CPU = CPU::from_program(program);
+        cpu.run().unwrap();
+        assert_eq!(cpu.registers[0], 55);
+    }
+
+    #[test]
+    fn errors_have_line_numbers() {
+        let error = |source: &str| assemble(source).unwrap_err();
+
+        assert_eq!(
+            error("#ip 0\nseti 1 0 1\naddj 1 2 3\n"),
+            AssembleError { line: 3, kind: AssembleErrorKind::UnknownOpcode("addj".to_string()) }
+        );
+        assert_eq!(
+            error("#ip 0\nseti 1 0\n"),
+            AssembleError { line: 2, kind: AssembleErrorKind::WrongOperandCount(2) }
+        );
+        assert_eq!(
+            error("#ip 0\n\naddr 1 7 1\n"),
+            AssembleError { line: 3, kind: AssembleErrorKind::InvalidRegister(7) }
+        );
+        assert_eq!(
+            error("#ip 0\nseti nowhere 0 0\n"),
+            AssembleError { line: 2, kind: AssembleErrorKind::UnknownLabel("nowhere".to_string()) }
+        );
+        assert_eq!(
+            error("; no directive\nseti 1 0 0\n"),
+            AssembleError { line: 2, kind: AssembleErrorKind::MissingInstructionPointer }
+        );
+        assert_eq!(
+            error("#ip 0\na: seti 1 0 0\na: seti 1 0 0\n"),
+            AssembleError { line: 3, kind: AssembleErrorKind::DuplicateLabel("a".to_string()) }
+        );
+        assert_eq!(error("#ip 0\n#ip 1\n").to_string(), "line 2: duplicate `#ip` directive");
+    }
+}
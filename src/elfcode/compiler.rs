@@ -0,0 +1,313 @@
+use super::{Instruction, Opcode, Overflow, Trap, Word, CPU};
+
+/// Executes an instruction, returning the next instruction pointer, or `None` if it traps.
+type Op = Box<dyn Fn(&mut [usize; 6]) -> Option<usize>>;
+
+#[derive(Debug, Clone, Copy)]
+enum Operand {
+    Register(usize),
+    Immediate(usize),
+}
+
+/// An Elfcode program pre-decoded into threaded code.
+///
+/// Every instruction is turned into a closure specialized for its opcode and operand kinds, which
+/// returns the index of the next instruction, or `None` if it traps. Since the instruction pointer
+/// register always holds the index of the current instruction, reads of it are folded into
+/// constants, and jumps to a constant target are resolved at compile time. Jumps that depend on
+/// other registers fall back to the interpreter for that single instruction.
+///
+/// The compiled program is a snapshot: patching `CPU::program` or changing `CPU::overflow`
+/// afterwards requires recompiling.
+pub struct CompiledProgram {
+    ops: Vec<Op>,
+    program: Vec<Instruction>,
+    instruction_pointer_index: usize,
+}
+
+fn binary<F>(f: F, a: Operand, b: Operand, c: usize, next: usize) -> Op
+where
+    F: Fn(usize, usize) -> Option<usize> + 'static,
+{
+    match (a, b) {
+        (Operand::Register(a), Operand::Register(b)) => {
+            Box::new(move |registers| match f(registers[a], registers[b]) {
+                Some(value) => {
+                    registers[c] = value;
+                    Some(next)
+                }
+                None => None,
+            })
+        }
+        (Operand::Register(a), Operand::Immediate(b)) => {
+            Box::new(move |registers| match f(registers[a], b) {
+                Some(value) => {
+                    registers[c] = value;
+                    Some(next)
+                }
+                None => None,
+            })
+        }
+        (Operand::Immediate(a), Operand::Register(b)) => {
+            Box::new(move |registers| match f(a, registers[b]) {
+                Some(value) => {
+                    registers[c] = value;
+                    Some(next)
+                }
+                None => None,
+            })
+        }
+        (Operand::Immediate(a), Operand::Immediate(b)) => match f(a, b) {
+            Some(value) => Box::new(move |registers| {
+                registers[c] = value;
+                Some(next)
+            }),
+            None => Box::new(|_| None),
+        },
+    }
+}
+
+fn compile_instruction(
+    instruction: Instruction,
+    instruction_pointer: usize,
+    instruction_pointer_index: usize,
+    overflow: Overflow,
+) -> Op {
+    let Instruction { opcode, a, b, c } = instruction;
+
+    let resolve = |value: usize, is_register: bool| {
+        if !is_register {
+            Operand::Immediate(value)
+        } else if value == instruction_pointer_index {
+            Operand::Immediate(instruction_pointer)
+        } else {
+            Operand::Register(value)
+        }
+    };
+
+    let a = resolve(a, opcode.reads_register_a());
+    let b =
+        if opcode.uses_b() { resolve(b, opcode.reads_register_b()) } else { Operand::Immediate(0) };
+
+    if c == instruction_pointer_index {
+        return match (a, b) {
+            (Operand::Immediate(a), Operand::Immediate(b)) => {
+                let next =
+                    opcode.apply(&a, &b, overflow).and_then(|target| target.add(&1, overflow));
+                Box::new(move |_| next)
+            }
+            _ => Box::new(move |registers| {
+                let previous = registers[instruction_pointer_index];
+                registers[instruction_pointer_index] = instruction_pointer;
+
+                let next = instruction
+                    .execute(registers, overflow)
+                    .and_then(|()| registers[instruction_pointer_index].add(&1, overflow));
+
+                if next.is_none() {
+                    registers[instruction_pointer_index] = previous;
+                }
+
+                next
+            }),
+        };
+    }
+
+    let next = instruction_pointer + 1;
+
+    match opcode {
+        Opcode::AddRegister | Opcode::AddImmediate => {
+            binary(move |a, b| a.add(&b, overflow), a, b, c, next)
+        }
+        Opcode::MultiplyRegister | Opcode::MultiplyImmediate => {
+            binary(move |a, b| a.mul(&b, overflow), a, b, c, next)
+        }
+        Opcode::BitwiseANDRegister | Opcode::BitwiseANDImmediate => {
+            binary(|a, b| Some(a & b), a, b, c, next)
+        }
+        Opcode::BitwiseORRegister | Opcode::BitwiseORImmediate => {
+            binary(|a, b| Some(a | b), a, b, c, next)
+        }
+        Opcode::SetRegister | Opcode::SetImmediate => binary(|a, _| Some(a), a, b, c, next),
+        Opcode::GreaterThanImmediateRegister
+        | Opcode::GreaterThanRegisterImmediate
+        | Opcode::GreaterThanRegisterRegister => {
+            binary(|a, b| Some((a > b) as usize), a, b, c, next)
+        }
+        Opcode::EqualImmediateRegister
+        | Opcode::EqualRegisterImmediate
+        | Opcode::EqualRegisterRegister => binary(|a, b| Some((a == b) as usize), a, b, c, next),
+    }
+}
+
+impl CompiledProgram {
+    pub fn new(
+        program: &[Instruction],
+        instruction_pointer_index: usize,
+        overflow: Overflow,
+    ) -> CompiledProgram {
+        let ops = program
+            .iter()
+            .enumerate()
+            .map(|(instruction_pointer, &instruction)| {
+                compile_instruction(
+                    instruction,
+                    instruction_pointer,
+                    instruction_pointer_index,
+                    overflow,
+                )
+            })
+            .collect();
+
+        CompiledProgram { ops, program: program.to_vec(), instruction_pointer_index }
+    }
+
+    fn trap(&self, instruction_pointer: usize) -> Trap {
+        Trap { instruction_pointer, instruction: self.program[instruction_pointer] }
+    }
+
+    /// Executes a single instruction, returning `Ok(false)` if the program has already halted.
+    pub fn step(&self, cpu: &mut CPU) -> Result<bool, Trap> {
+        let instruction_pointer = cpu.instruction_pointer();
+
+        if let Some(op) = self.ops.get(instruction_pointer) {
+            match op(&mut cpu.registers) {
+                Some(next) => cpu.registers[self.instruction_pointer_index] = next,
+                None => return Err(self.trap(instruction_pointer)),
+            }
+            cpu.executed_instructions += 1;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Runs until the instruction pointer reaches `breakpoint`, returning `Ok(false)` if the
+    /// program halted first. At least one instruction is always executed, so calling this
+    /// repeatedly visits every hit of the breakpoint.
+    pub fn run_until(&self, cpu: &mut CPU, breakpoint: usize) -> Result<bool, Trap> {
+        self.run_to(cpu, Some(breakpoint))
+    }
+
+    pub fn run(&self, cpu: &mut CPU) -> Result<(), Trap> {
+        self.run_to(cpu, None).map(|_| ())
+    }
+
+    fn run_to(&self, cpu: &mut CPU, breakpoint: Option<usize>) -> Result<bool, Trap> {
+        let mut instruction_pointer = cpu.instruction_pointer();
+        let mut executed_instructions = 0;
+        let mut result = Ok(());
+
+        while let Some(op) = self.ops.get(instruction_pointer) {
+            match op(&mut cpu.registers) {
+                Some(next) => instruction_pointer = next,
+                None => {
+                    result = Err(self.trap(instruction_pointer));
+                    break;
+                }
+            }
+
+            executed_instructions += 1;
+
+            if Some(instruction_pointer) == breakpoint {
+                break;
+            }
+        }
+
+        cpu.registers[self.instruction_pointer_index] = instruction_pointer;
+        cpu.executed_instructions += executed_instructions;
+
+        result.map(|()| instruction_pointer < self.ops.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    const DAY19_TEST_INPUT: &str = include_str!("../day19/test_input");
+    const DAY19_INPUT: &str = include_str!("../day19/input");
+    const DAY21_INPUT: &str = include_str!("../day21/input");
+
+    fn assert_equivalent(input: &str, max_steps: usize) {
+        assert_equivalent_with(input, max_steps, Overflow::default());
+    }
+
+    fn assert_equivalent_with(input: &str, max_steps: usize, overflow: Overflow) {
+        let mut interpreted = CPU::from_input(input).with_overflow(overflow);
+        let mut compiled = interpreted.clone();
+        let program = compiled.compile();
+
+        for _ in 0..max_steps {
+            let running = interpreted.step().unwrap();
+            assert_eq!(program.step(&mut compiled), Ok(running));
+            assert_eq!(compiled.registers, interpreted.registers);
+
+            if !running {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn compiled_matches_interpreter() {
+        assert_equivalent(DAY19_TEST_INPUT, 100);
+        assert_equivalent(DAY19_INPUT, 100_000);
+        assert_equivalent(DAY21_INPUT, 100_000);
+    }
+
+    #[test]
+    fn jumps_to_the_last_address_halt() {
+        let last = std::usize::MAX;
+
+        // Both a constant jump and one through another register land on `usize::MAX`. Getting
+        // there doesn't overflow, so it's past the end of the program and halts in every mode
+        for input in &[
+            format!("#ip 0\nseti {} 0 0\n", last - 1),
+            format!("#ip 0\nseti {} 0 1\naddr 1 2 0\n", last - 1),
+        ] {
+            for &overflow in &[Overflow::Wrapping, Overflow::Saturating, Overflow::Trapping] {
+                assert_equivalent_with(input, 10, overflow);
+
+                let mut cpu = CPU::from_input(input).with_overflow(overflow);
+                assert_eq!(cpu.compile().run(&mut cpu), Ok(()));
+                assert_eq!(cpu.instruction_pointer(), last);
+            }
+        }
+    }
+
+    #[test]
+    fn run_until_stops_at_breakpoint() {
+        let mut cpu = CPU::from_input(DAY21_INPUT);
+        let program = cpu.compile();
+
+        assert_eq!(program.run_until(&mut cpu, 28), Ok(true));
+        assert_eq!(cpu.instruction_pointer(), 28);
+        assert_eq!(cpu.registers[3], 7_967_233);
+    }
+
+    // Run with `cargo test --release -- --ignored --nocapture` to compare the two backends.
+    #[test]
+    #[ignore]
+    fn benchmark_against_interpreter() {
+        let mut interpreted = CPU::from_input(DAY19_INPUT);
+        let mut compiled = interpreted.clone();
+
+        let start = Instant::now();
+        interpreted.run().unwrap();
+        let interpreter_time = start.elapsed();
+
+        let start = Instant::now();
+        compiled.compile().run(&mut compiled).unwrap();
+        let compiled_time = start.elapsed();
+
+        assert_eq!(compiled.registers, interpreted.registers);
+        assert_eq!(compiled.executed_instructions, interpreted.executed_instructions);
+
+        println!(
+            "{} instructions: interpreter {:?}, compiled {:?}",
+            interpreted.executed_instructions, interpreter_time, compiled_time
+        );
+    }
+}
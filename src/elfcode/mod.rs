@@ -0,0 +1,318 @@
+use core::fmt;
+use core::num::ParseIntError;
+use core::str::FromStr;
+
+mod assembler;
+mod compiler;
+mod symbolic;
+mod word;
+
+pub use self::assembler::{assemble, AssembleError, AssembleErrorKind, Program};
+pub use self::compiler::CompiledProgram;
+pub use self::symbolic::{
+    loop_invariant_registers, run_to_loop_head, Operation, Stop, SymbolicState, Value,
+};
+pub use self::word::{Overflow, Word};
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Opcode {
+    AddRegister,
+    AddImmediate,
+    MultiplyRegister,
+    MultiplyImmediate,
+    BitwiseANDRegister,
+    BitwiseANDImmediate,
+    BitwiseORRegister,
+    BitwiseORImmediate,
+    SetRegister,
+    SetImmediate,
+    GreaterThanImmediateRegister,
+    GreaterThanRegisterImmediate,
+    GreaterThanRegisterRegister,
+    EqualImmediateRegister,
+    EqualRegisterImmediate,
+    EqualRegisterRegister,
+}
+
+impl Opcode {
+    /// Whether operand `a` names a register (as opposed to being an immediate value).
+    pub fn reads_register_a(self) -> bool {
+        match self {
+            Opcode::SetImmediate
+            | Opcode::GreaterThanImmediateRegister
+            | Opcode::EqualImmediateRegister => false,
+            _ => true,
+        }
+    }
+
+    /// Whether operand `b` names a register. `setr` and `seti` ignore `b` completely.
+    pub fn reads_register_b(self) -> bool {
+        match self {
+            Opcode::AddRegister
+            | Opcode::MultiplyRegister
+            | Opcode::BitwiseANDRegister
+            | Opcode::BitwiseORRegister
+            | Opcode::GreaterThanImmediateRegister
+            | Opcode::GreaterThanRegisterRegister
+            | Opcode::EqualImmediateRegister
+            | Opcode::EqualRegisterRegister => true,
+            _ => false,
+        }
+    }
+
+    /// Whether operand `b` is used at all.
+    pub fn uses_b(self) -> bool {
+        match self {
+            Opcode::SetRegister | Opcode::SetImmediate => false,
+            _ => true,
+        }
+    }
+
+    /// Applies the operation to already resolved operand values, returning `None` if it traps.
+    #[inline]
+    pub fn apply<W: Word>(self, a: &W, b: &W, overflow: Overflow) -> Option<W> {
+        match self {
+            Opcode::AddRegister | Opcode::AddImmediate => a.add(b, overflow),
+            Opcode::MultiplyRegister | Opcode::MultiplyImmediate => a.mul(b, overflow),
+            Opcode::BitwiseANDRegister | Opcode::BitwiseANDImmediate => Some(a.bitand(b)),
+            Opcode::BitwiseORRegister | Opcode::BitwiseORImmediate => Some(a.bitor(b)),
+            Opcode::SetRegister | Opcode::SetImmediate => Some(a.clone()),
+            Opcode::GreaterThanImmediateRegister
+            | Opcode::GreaterThanRegisterImmediate
+            | Opcode::GreaterThanRegisterRegister => Some(W::from_bool(a > b)),
+            Opcode::EqualImmediateRegister
+            | Opcode::EqualRegisterImmediate
+            | Opcode::EqualRegisterRegister => Some(W::from_bool(a == b)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseOpcodeError;
+
+impl FromStr for Opcode {
+    type Err = ParseOpcodeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "addr" => Ok(Opcode::AddRegister),
+            "addi" => Ok(Opcode::AddImmediate),
+            "mulr" => Ok(Opcode::MultiplyRegister),
+            "muli" => Ok(Opcode::MultiplyImmediate),
+            "banr" => Ok(Opcode::BitwiseANDRegister),
+            "bani" => Ok(Opcode::BitwiseANDImmediate),
+            "borr" => Ok(Opcode::BitwiseORRegister),
+            "bori" => Ok(Opcode::BitwiseORImmediate),
+            "setr" => Ok(Opcode::SetRegister),
+            "seti" => Ok(Opcode::SetImmediate),
+            "gtir" => Ok(Opcode::GreaterThanImmediateRegister),
+            "gtri" => Ok(Opcode::GreaterThanRegisterImmediate),
+            "gtrr" => Ok(Opcode::GreaterThanRegisterRegister),
+            "eqir" => Ok(Opcode::EqualImmediateRegister),
+            "eqri" => Ok(Opcode::EqualRegisterImmediate),
+            "eqrr" => Ok(Opcode::EqualRegisterRegister),
+            _ => Err(ParseOpcodeError),
+        }
+    }
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Opcode::AddRegister => "addr",
+                Opcode::AddImmediate => "addi",
+                Opcode::MultiplyRegister => "mulr",
+                Opcode::MultiplyImmediate => "muli",
+                Opcode::BitwiseANDRegister => "banr",
+                Opcode::BitwiseANDImmediate => "bani",
+                Opcode::BitwiseORRegister => "borr",
+                Opcode::BitwiseORImmediate => "bori",
+                Opcode::SetRegister => "setr",
+                Opcode::SetImmediate => "seti",
+                Opcode::GreaterThanImmediateRegister => "gtir",
+                Opcode::GreaterThanRegisterImmediate => "gtri",
+                Opcode::GreaterThanRegisterRegister => "gtrr",
+                Opcode::EqualImmediateRegister => "eqir",
+                Opcode::EqualRegisterImmediate => "eqri",
+                Opcode::EqualRegisterRegister => "eqrr",
+            }
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct Instruction {
+    pub opcode: Opcode,
+    pub a: usize,
+    pub b: usize,
+    pub c: usize,
+}
+
+#[derive(Debug)]
+pub enum ParseInstructionError {
+    TooShort,
+    Int(ParseIntError),
+    Opcode(ParseOpcodeError),
+}
+
+impl From<ParseOpcodeError> for ParseInstructionError {
+    fn from(error: ParseOpcodeError) -> ParseInstructionError {
+        ParseInstructionError::Opcode(error)
+    }
+}
+
+impl From<ParseIntError> for ParseInstructionError {
+    fn from(error: ParseIntError) -> ParseInstructionError {
+        ParseInstructionError::Int(error)
+    }
+}
+
+impl FromStr for Instruction {
+    type Err = ParseInstructionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut it = s.splitn(4, char::is_whitespace);
+        Ok(Instruction {
+            opcode: Opcode::from_str(it.next().ok_or(ParseInstructionError::TooShort)?)?,
+            a: it.next().ok_or(ParseInstructionError::TooShort)?.parse()?,
+            b: it.next().ok_or(ParseInstructionError::TooShort)?.parse()?,
+            c: it.next().ok_or(ParseInstructionError::TooShort)?.parse()?,
+        })
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {} {}", self.opcode, self.a, self.b, self.c)
+    }
+}
+
+impl Instruction {
+    pub fn reads_register(&self, register: usize) -> bool {
+        (self.opcode.reads_register_a() && self.a == register)
+            || (self.opcode.reads_register_b() && self.b == register)
+    }
+
+    /// Executes the instruction on a set of registers, returning `None` if it traps. The
+    /// registers are left untouched in that case.
+    #[inline]
+    pub fn execute<W: Word>(self, registers: &mut [W; 6], overflow: Overflow) -> Option<()> {
+        let Instruction { opcode, a, b, c } = self;
+        let operand = |value: usize, is_register: bool| {
+            if is_register {
+                Some(registers[value].clone())
+            } else {
+                W::from_immediate(value, overflow)
+            }
+        };
+
+        let a = operand(a, opcode.reads_register_a())?;
+        let b = if opcode.uses_b() { operand(b, opcode.reads_register_b())? } else { W::default() };
+        registers[c] = opcode.apply(&a, &b, overflow)?;
+        Some(())
+    }
+}
+
+/// A runtime error raised by an instruction that overflowed with `Overflow::Trapping`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Trap {
+    pub instruction_pointer: usize,
+    pub instruction: Instruction,
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "arithmetic overflow at instruction {} (`{}`)",
+            self.instruction_pointer, self.instruction
+        )
+    }
+}
+
+/// An Elfcode machine with registers of type `W`.
+#[derive(Debug, Clone)]
+pub struct Machine<W> {
+    pub registers: [W; 6],
+    pub executed_instructions: usize,
+    pub instruction_pointer_index: usize,
+    pub program: Vec<Instruction>,
+    pub overflow: Overflow,
+}
+
+/// The machine used by the puzzles, which fits all of their values in a `usize`.
+pub type CPU = Machine<usize>;
+
+impl<W: Word> Machine<W> {
+    pub fn from_program(program: Program) -> Machine<W> {
+        let Program { instruction_pointer_index, instructions } = program;
+
+        Machine {
+            instruction_pointer_index,
+            program: instructions,
+            executed_instructions: 0,
+            registers: Default::default(),
+            overflow: Overflow::default(),
+        }
+    }
+
+    pub fn from_input(input: &str) -> Machine<W> {
+        match assemble(input) {
+            Ok(program) => Machine::from_program(program),
+            Err(error) => panic!("Invalid program: {}", error),
+        }
+    }
+
+    pub fn with_overflow(self, overflow: Overflow) -> Machine<W> {
+        Machine { overflow, ..self }
+    }
+
+    /// The current instruction pointer, or the largest `usize` if it doesn't even fit in one.
+    pub fn instruction_pointer(&self) -> usize {
+        self.registers[self.instruction_pointer_index].to_usize().unwrap_or(usize::max_value())
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.instruction_pointer() >= self.program.len()
+    }
+
+    /// Executes a single instruction, returning `Ok(false)` if the program has already halted.
+    pub fn step(&mut self) -> Result<bool, Trap> {
+        let instruction_pointer = self.instruction_pointer();
+
+        let instruction = match self.program.get(instruction_pointer) {
+            Some(&instruction) => instruction,
+            None => return Ok(false),
+        };
+
+        let trap = Trap { instruction_pointer, instruction };
+        let mut registers = self.registers.clone();
+
+        instruction.execute(&mut registers, self.overflow).ok_or(trap)?;
+
+        let next = registers[self.instruction_pointer_index]
+            .add(&W::from_bool(true), self.overflow)
+            .ok_or(trap)?;
+
+        registers[self.instruction_pointer_index] = next;
+        self.registers = registers;
+        self.executed_instructions += 1;
+
+        Ok(true)
+    }
+
+    pub fn run(&mut self) -> Result<(), Trap> {
+        while self.step()? {}
+        Ok(())
+    }
+}
+
+impl CPU {
+    /// Pre-decodes the program into threaded code. See `CompiledProgram`.
+    pub fn compile(&self) -> CompiledProgram {
+        CompiledProgram::new(&self.program, self.instruction_pointer_index, self.overflow)
+    }
+}
@@ -0,0 +1,330 @@
+use super::{Instruction, Opcode, Overflow, CPU};
+use core::fmt;
+use std::collections::BTreeSet;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Operation {
+    Add,
+    Multiply,
+    BitwiseAND,
+    BitwiseOR,
+    GreaterThan,
+    Equal,
+}
+
+/// A register value during symbolic execution: either known, the unknown initial value of a
+/// register, or an operation on other values.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum Value {
+    Constant(usize),
+    Input(usize),
+    Binary(Operation, Box<Value>, Box<Value>),
+}
+
+impl Operation {
+    fn from_opcode(opcode: Opcode) -> Option<Operation> {
+        match opcode {
+            Opcode::AddRegister | Opcode::AddImmediate => Some(Operation::Add),
+            Opcode::MultiplyRegister | Opcode::MultiplyImmediate => Some(Operation::Multiply),
+            Opcode::BitwiseANDRegister | Opcode::BitwiseANDImmediate => Some(Operation::BitwiseAND),
+            Opcode::BitwiseORRegister | Opcode::BitwiseORImmediate => Some(Operation::BitwiseOR),
+            Opcode::SetRegister | Opcode::SetImmediate => None,
+            Opcode::GreaterThanImmediateRegister
+            | Opcode::GreaterThanRegisterImmediate
+            | Opcode::GreaterThanRegisterRegister => Some(Operation::GreaterThan),
+            Opcode::EqualImmediateRegister
+            | Opcode::EqualRegisterImmediate
+            | Opcode::EqualRegisterRegister => Some(Operation::Equal),
+        }
+    }
+
+    fn apply(self, a: usize, b: usize) -> usize {
+        match self {
+            Operation::Add => a.wrapping_add(b),
+            Operation::Multiply => a.wrapping_mul(b),
+            Operation::BitwiseAND => a & b,
+            Operation::BitwiseOR => a | b,
+            Operation::GreaterThan => (a > b) as usize,
+            Operation::Equal => (a == b) as usize,
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            Operation::Add => "+",
+            Operation::Multiply => "*",
+            Operation::BitwiseAND => "&",
+            Operation::BitwiseOR => "|",
+            Operation::GreaterThan => ">",
+            Operation::Equal => "==",
+        }
+    }
+}
+
+impl Value {
+    pub fn constant(&self) -> Option<usize> {
+        match self {
+            Value::Constant(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Builds `a <operation> b`, folding constants and trivial identities.
+    fn binary(operation: Operation, a: Value, b: Value) -> Value {
+        match (operation, a, b) {
+            (_, Value::Constant(a), Value::Constant(b)) => Value::Constant(operation.apply(a, b)),
+            (Operation::Add, Value::Constant(0), x)
+            | (Operation::Add, x, Value::Constant(0))
+            | (Operation::Multiply, Value::Constant(1), x)
+            | (Operation::Multiply, x, Value::Constant(1))
+            | (Operation::BitwiseOR, Value::Constant(0), x)
+            | (Operation::BitwiseOR, x, Value::Constant(0)) => x,
+            (Operation::Multiply, Value::Constant(0), _)
+            | (Operation::Multiply, _, Value::Constant(0))
+            | (Operation::BitwiseAND, Value::Constant(0), _)
+            | (Operation::BitwiseAND, _, Value::Constant(0)) => Value::Constant(0),
+            (Operation::Equal, ref a, ref b) if a == b => Value::Constant(1),
+            (Operation::GreaterThan, ref a, ref b) if a == b => Value::Constant(0),
+            (_, a, b) => Value::Binary(operation, Box::new(a), Box::new(b)),
+        }
+    }
+
+    /// The initial registers of a run where every register value is unknown.
+    pub fn inputs() -> [Value; 6] {
+        [
+            Value::Input(0),
+            Value::Input(1),
+            Value::Input(2),
+            Value::Input(3),
+            Value::Input(4),
+            Value::Input(5),
+        ]
+    }
+
+    pub fn constants(registers: [usize; 6]) -> [Value; 6] {
+        let [a, b, c, d, e, f] = registers;
+        [
+            Value::Constant(a),
+            Value::Constant(b),
+            Value::Constant(c),
+            Value::Constant(d),
+            Value::Constant(e),
+            Value::Constant(f),
+        ]
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Constant(value) => write!(f, "{}", value),
+            Value::Input(register) => write!(f, "r{}", register),
+            Value::Binary(operation, a, b) => write!(f, "({} {} {})", a, operation.symbol(), b),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Stop {
+    /// The program jumped backwards, to the instruction pointer in the state.
+    LoopHead,
+    Halted,
+    /// The instruction at the instruction pointer would jump to a target that isn't known.
+    SymbolicJump,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SymbolicState {
+    pub instruction_pointer: usize,
+    pub registers: [Value; 6],
+    pub executed_instructions: usize,
+    pub stop: Stop,
+}
+
+/// Executes the program with symbolic registers until it first jumps backwards, which is where
+/// straight-line setup code typically ends and the actual work begins.
+///
+/// Since execution only ever moves forward before that, this is bounded by the program length.
+pub fn run_to_loop_head(cpu: &CPU, registers: [Value; 6]) -> SymbolicState {
+    let instruction_pointer_index = cpu.instruction_pointer_index;
+    let mut registers = registers;
+    let mut executed_instructions = 0;
+
+    let stop = |instruction_pointer, registers, executed_instructions, stop| SymbolicState {
+        instruction_pointer,
+        registers,
+        executed_instructions,
+        stop,
+    };
+
+    let mut instruction_pointer = match registers[instruction_pointer_index].constant() {
+        Some(instruction_pointer) => instruction_pointer,
+        None => return stop(0, registers, 0, Stop::SymbolicJump),
+    };
+
+    while let Some(&Instruction { opcode, a, b, c }) = cpu.program.get(instruction_pointer) {
+        registers[instruction_pointer_index] = Value::Constant(instruction_pointer);
+
+        let a = if opcode.reads_register_a() { registers[a].clone() } else { Value::Constant(a) };
+        let result = match Operation::from_opcode(opcode) {
+            Some(operation) => {
+                let b = if opcode.reads_register_b() {
+                    registers[b].clone()
+                } else {
+                    Value::Constant(b)
+                };
+                Value::binary(operation, a, b)
+            }
+            None => a,
+        };
+
+        let next = if c == instruction_pointer_index {
+            match result.constant() {
+                Some(target) => target.wrapping_add(1),
+                None => {
+                    return stop(
+                        instruction_pointer,
+                        registers,
+                        executed_instructions,
+                        Stop::SymbolicJump,
+                    )
+                }
+            }
+        } else {
+            registers[c] = result;
+            instruction_pointer + 1
+        };
+
+        executed_instructions += 1;
+        registers[instruction_pointer_index] = Value::Constant(next);
+
+        if next <= instruction_pointer {
+            return stop(next, registers, executed_instructions, Stop::LoopHead);
+        }
+
+        instruction_pointer = next;
+    }
+
+    stop(instruction_pointer, registers, executed_instructions, Stop::Halted)
+}
+
+/// The possible successors of an instruction, or `None` if they can't be determined statically.
+/// A jump by `addr X ip ip` counts as a conditional skip when `X` was just set by a comparison.
+fn successors(cpu: &CPU, instruction_pointer: usize) -> Option<Vec<usize>> {
+    let ip = cpu.instruction_pointer_index;
+    let Instruction { opcode, a, b, c } = cpu.program[instruction_pointer];
+
+    if c != ip {
+        return Some(vec![instruction_pointer + 1]);
+    }
+
+    let resolve = |value: usize, is_register: bool| match (is_register, value == ip) {
+        (false, _) => Some(value),
+        (true, true) => Some(instruction_pointer),
+        (true, false) => None,
+    };
+
+    let a_value = resolve(a, opcode.reads_register_a());
+    let b_value = if opcode.uses_b() { resolve(b, opcode.reads_register_b()) } else { Some(0) };
+
+    if let (Some(a), Some(b)) = (a_value, b_value) {
+        return Some(vec![opcode.apply(&a, &b, Overflow::Wrapping)?.wrapping_add(1)]);
+    }
+
+    let condition = match (opcode, a_value, b_value) {
+        (Opcode::AddRegister, None, Some(_)) => a,
+        (Opcode::AddRegister, Some(_), None) => b,
+        _ => return None,
+    };
+
+    let previous = cpu.program.get(instruction_pointer.checked_sub(1)?)?;
+    let is_comparison = match Operation::from_opcode(previous.opcode) {
+        Some(Operation::GreaterThan) | Some(Operation::Equal) => true,
+        _ => false,
+    };
+
+    if previous.c == condition && is_comparison {
+        Some(vec![instruction_pointer + 1, instruction_pointer + 2])
+    } else {
+        None
+    }
+}
+
+/// Finds the registers that the code reachable from `loop_head` reads but never writes, i.e. the
+/// inputs that the loop was set up with. Returns `None` if the control flow can't be determined.
+pub fn loop_invariant_registers(cpu: &CPU, loop_head: usize) -> Option<BTreeSet<usize>> {
+    let mut visited = BTreeSet::new();
+    let mut queue = vec![loop_head];
+
+    while let Some(instruction_pointer) = queue.pop() {
+        if instruction_pointer < cpu.program.len() && visited.insert(instruction_pointer) {
+            queue.extend(successors(cpu, instruction_pointer)?);
+        }
+    }
+
+    let mut read = BTreeSet::new();
+    let mut written = BTreeSet::new();
+
+    for &instruction_pointer in &visited {
+        let instruction = cpu.program[instruction_pointer];
+        written.insert(instruction.c);
+        read.extend((0..6).filter(|&register| instruction.reads_register(register)));
+    }
+
+    written.insert(cpu.instruction_pointer_index);
+
+    Some(read.difference(&written).cloned().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY19_INPUT: &str = include_str!("../day19/input");
+
+    #[test]
+    fn symbolic_input_stops_at_unknown_jump() {
+        let cpu = CPU::from_input(DAY19_INPUT);
+        let mut registers = Value::constants([0; 6]);
+        registers[0] = Value::Input(0);
+
+        let state = run_to_loop_head(&cpu, registers);
+
+        assert_eq!(state.stop, Stop::SymbolicJump);
+        assert_eq!(state.instruction_pointer, 25);
+        assert_eq!(state.registers[0], Value::Input(0));
+        assert_eq!(state.registers[3].constant(), Some(1008));
+    }
+
+    #[test]
+    fn concrete_input_reaches_loop_head() {
+        let cpu = CPU::from_input(DAY19_INPUT);
+        let state = run_to_loop_head(&cpu, Value::constants([1, 0, 0, 0, 0, 0]));
+
+        assert_eq!(state.stop, Stop::LoopHead);
+        assert_eq!(state.instruction_pointer, 1);
+        assert_eq!(state.registers[3], Value::Constant(10_551_408));
+    }
+
+    #[test]
+    fn loop_invariant_registers_work() {
+        let cpu = CPU::from_input(DAY19_INPUT);
+
+        assert_eq!(loop_invariant_registers(&cpu, 1), Some(vec![3].into_iter().collect()));
+    }
+
+    #[test]
+    fn values_display_as_expressions() {
+        let value = Value::binary(
+            Operation::Multiply,
+            Value::binary(Operation::Add, Value::Input(0), Value::Constant(2)),
+            Value::Input(3),
+        );
+
+        assert_eq!(value.to_string(), "((r0 + 2) * r3)");
+        assert_eq!(
+            Value::binary(Operation::Multiply, value, Value::Constant(0)),
+            Value::Constant(0)
+        );
+    }
+}
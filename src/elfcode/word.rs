@@ -0,0 +1,185 @@
+use core::fmt;
+use core::hash::Hash;
+use num::{BigUint, FromPrimitive, ToPrimitive};
+
+/// What happens when an arithmetic result doesn't fit in a register.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Overflow {
+    Wrapping,
+    Saturating,
+    /// Stop execution with a `Trap` at the faulting instruction.
+    Trapping,
+}
+
+impl Default for Overflow {
+    fn default() -> Overflow {
+        Overflow::Trapping
+    }
+}
+
+/// A register word. Operations return `None` when they overflow with `Overflow::Trapping`.
+pub trait Word: Clone + Eq + Ord + Hash + Default + fmt::Debug + fmt::Display {
+    /// Converts an immediate operand into a word, which can itself overflow for narrow words.
+    fn from_immediate(value: usize, overflow: Overflow) -> Option<Self>;
+    fn to_usize(&self) -> Option<usize>;
+    fn add(&self, other: &Self, overflow: Overflow) -> Option<Self>;
+    fn mul(&self, other: &Self, overflow: Overflow) -> Option<Self>;
+    fn bitand(&self, other: &Self) -> Self;
+    fn bitor(&self, other: &Self) -> Self;
+
+    fn from_bool(value: bool) -> Self {
+        Self::from_immediate(value as usize, Overflow::Wrapping).unwrap()
+    }
+}
+
+macro_rules! impl_word {
+    ($($t:ty),*) => {
+        $(
+            impl Word for $t {
+                #[inline]
+                fn from_immediate(value: usize, overflow: Overflow) -> Option<Self> {
+                    match overflow {
+                        Overflow::Wrapping => Some(value as $t),
+                        Overflow::Saturating => {
+                            Some(<$t>::from_usize(value).unwrap_or(<$t>::max_value()))
+                        }
+                        Overflow::Trapping => <$t>::from_usize(value),
+                    }
+                }
+
+                #[inline]
+                fn to_usize(&self) -> Option<usize> {
+                    ToPrimitive::to_usize(self)
+                }
+
+                #[inline]
+                fn add(&self, other: &Self, overflow: Overflow) -> Option<Self> {
+                    match overflow {
+                        Overflow::Wrapping => Some(self.wrapping_add(*other)),
+                        Overflow::Saturating => Some(self.saturating_add(*other)),
+                        Overflow::Trapping => self.checked_add(*other),
+                    }
+                }
+
+                #[inline]
+                fn mul(&self, other: &Self, overflow: Overflow) -> Option<Self> {
+                    match overflow {
+                        Overflow::Wrapping => Some(self.wrapping_mul(*other)),
+                        Overflow::Saturating => Some(self.saturating_mul(*other)),
+                        Overflow::Trapping => self.checked_mul(*other),
+                    }
+                }
+
+                #[inline]
+                fn bitand(&self, other: &Self) -> Self {
+                    self & other
+                }
+
+                #[inline]
+                fn bitor(&self, other: &Self) -> Self {
+                    self | other
+                }
+            }
+        )*
+    };
+}
+
+impl_word!(u32, u64, u128, usize);
+
+/// Arbitrary precision registers, which can never overflow.
+impl Word for BigUint {
+    fn from_immediate(value: usize, _overflow: Overflow) -> Option<Self> {
+        Some(BigUint::from(value))
+    }
+
+    fn to_usize(&self) -> Option<usize> {
+        ToPrimitive::to_usize(self)
+    }
+
+    fn add(&self, other: &Self, _overflow: Overflow) -> Option<Self> {
+        Some(self + other)
+    }
+
+    fn mul(&self, other: &Self, _overflow: Overflow) -> Option<Self> {
+        Some(self * other)
+    }
+
+    fn bitand(&self, other: &Self) -> Self {
+        self & other
+    }
+
+    fn bitor(&self, other: &Self) -> Self {
+        self | other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elfcode::{Instruction, Machine, Opcode, Trap, CPU};
+
+    const DAY19_TEST_INPUT: &str = include_str!("../day19/test_input");
+
+    // Squares register 0 five times, starting at 2^4, which reaches 2^128
+    const SQUARING: &str =
+        "#ip 5\nseti 16 0 0\nmulr 0 0 0\nmulr 0 0 0\nmulr 0 0 0\nmulr 0 0 0\nmulr 0 0 0\n";
+
+    fn run<W: Word>(input: &str, overflow: Overflow) -> Result<W, Trap> {
+        let mut machine = Machine::<W>::from_input(input).with_overflow(overflow);
+        machine.run()?;
+        Ok(machine.registers[0].clone())
+    }
+
+    #[test]
+    fn all_words_run_programs() {
+        assert_eq!(run::<u32>(DAY19_TEST_INPUT, Overflow::Trapping), Ok(7));
+        assert_eq!(run::<u64>(DAY19_TEST_INPUT, Overflow::Trapping), Ok(7));
+        assert_eq!(run::<u128>(DAY19_TEST_INPUT, Overflow::Trapping), Ok(7));
+        assert_eq!(run::<BigUint>(DAY19_TEST_INPUT, Overflow::Trapping), Ok(BigUint::from(7u32)));
+    }
+
+    #[test]
+    fn overflow_modes_work() {
+        let input = "#ip 5\nseti 4294967295 0 0\naddi 0 1 0\n";
+
+        assert_eq!(run::<u32>(input, Overflow::Wrapping), Ok(0));
+        assert_eq!(run::<u32>(input, Overflow::Saturating), Ok(u32::max_value()));
+        assert_eq!(
+            run::<u32>(input, Overflow::Trapping),
+            Err(Trap {
+                instruction_pointer: 1,
+                instruction: Instruction { opcode: Opcode::AddImmediate, a: 0, b: 1, c: 0 },
+            })
+        );
+        assert_eq!(run::<u64>(input, Overflow::Trapping), Ok(1 << 32));
+    }
+
+    #[test]
+    fn traps_leave_registers_untouched() {
+        let mut machine = Machine::<u128>::from_input(SQUARING);
+
+        let trap = machine.run().unwrap_err();
+
+        assert_eq!(trap.instruction_pointer, 5);
+        assert_eq!(trap.to_string(), "arithmetic overflow at instruction 5 (`mulr 0 0 0`)");
+        assert_eq!(machine.instruction_pointer(), 5);
+        assert_eq!(machine.registers[0], 1 << 64);
+
+        assert_eq!(run::<BigUint>(SQUARING, Overflow::Trapping), Ok(BigUint::from(1u32) << 128));
+    }
+
+    #[test]
+    fn compiled_programs_trap() {
+        let mut cpu = CPU::from_input(SQUARING);
+        let trap = cpu.compile().run(&mut cpu).unwrap_err();
+
+        assert_eq!(trap.instruction_pointer, 4);
+        assert_eq!(cpu.instruction_pointer(), 4);
+        assert_eq!(cpu.registers[0], 1 << 32);
+
+        let mut cpu = CPU::from_input(SQUARING).with_overflow(Overflow::Saturating);
+        cpu.compile().run(&mut cpu).unwrap();
+
+        assert_eq!(cpu.registers[0], usize::max_value());
+    }
+}
@@ -1,5 +1,6 @@
 #![deny(clippy::all)]
 
+pub mod automaton;
 pub mod day01;
 pub mod day02;
 pub mod day03;
@@ -21,3 +22,5 @@ pub mod day19;
 pub mod day20;
 pub mod day21;
 pub mod day22;
+pub mod elfcode;
+pub mod ring;
@@ -0,0 +1,194 @@
+struct Node<T> {
+    value: Option<T>,
+    prev: usize,
+    next: usize,
+}
+
+/// A circular doubly-linked list with a cursor at its current element.
+///
+/// The nodes live in a vector and link to each other by index. Removed nodes are reused by later
+/// insertions, so moving, inserting and removing are all O(1) without allocating per element.
+pub struct Ring<T> {
+    nodes: Vec<Node<T>>,
+    free: Vec<usize>,
+    current: Option<usize>,
+    len: usize,
+}
+
+impl<T> Ring<T> {
+    pub fn new() -> Ring<T> {
+        Ring::with_capacity(0)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Ring<T> {
+        Ring { nodes: Vec::with_capacity(capacity), free: Vec::new(), current: None, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn current(&self) -> Option<&T> {
+        self.current.and_then(|index| self.nodes[index].value.as_ref())
+    }
+
+    /// Moves the cursor `steps` elements clockwise.
+    pub fn clockwise(&mut self, steps: usize) {
+        if let Some(mut index) = self.current {
+            for _ in 0..steps % self.len {
+                index = self.nodes[index].next;
+            }
+            self.current = Some(index);
+        }
+    }
+
+    /// Moves the cursor `steps` elements counter-clockwise.
+    pub fn counter_clockwise(&mut self, steps: usize) {
+        if let Some(mut index) = self.current {
+            for _ in 0..steps % self.len {
+                index = self.nodes[index].prev;
+            }
+            self.current = Some(index);
+        }
+    }
+
+    /// Inserts a value clockwise of the current element, and makes it the current element.
+    pub fn insert(&mut self, value: T) {
+        let (prev, next) = match self.current {
+            Some(current) => (current, self.nodes[current].next),
+            None => (self.nodes.len(), self.nodes.len()),
+        };
+
+        let node = Node { value: Some(value), prev, next };
+
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+
+        if self.current.is_none() {
+            // The only node links to itself, wherever it ended up
+            self.nodes[index].prev = index;
+            self.nodes[index].next = index;
+        } else {
+            self.nodes[prev].next = index;
+            self.nodes[next].prev = index;
+        }
+
+        self.current = Some(index);
+        self.len += 1;
+    }
+
+    /// Removes the current element, and makes the element clockwise of it the current one.
+    pub fn remove(&mut self) -> Option<T> {
+        let index = self.current?;
+        let Node { prev, next, .. } = self.nodes[index];
+
+        self.nodes[prev].next = next;
+        self.nodes[next].prev = prev;
+        self.free.push(index);
+        self.len -= 1;
+        self.current = if self.len == 0 { None } else { Some(next) };
+
+        self.nodes[index].value.take()
+    }
+
+    /// The elements in clockwise order, starting at the current one.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        let mut index = self.current;
+
+        (0..self.len).filter_map(move |_| {
+            let node = &self.nodes[index?];
+            index = Some(node.next);
+            node.value.as_ref()
+        })
+    }
+}
+
+impl<T> Default for Ring<T> {
+    fn default() -> Ring<T> {
+        Ring::new()
+    }
+}
+
+impl<T> std::iter::FromIterator<T> for Ring<T> {
+    /// A ring of the values in clockwise order, where the last value is the current element.
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Ring<T> {
+        let mut ring = Ring::new();
+
+        for value in iter {
+            ring.insert(value);
+        }
+
+        ring
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(ring: &Ring<usize>) -> Vec<usize> {
+        ring.iter().cloned().collect()
+    }
+
+    #[test]
+    fn moving_works() {
+        let mut ring = (0..5).collect::<Ring<_>>();
+
+        assert_eq!(ring.len(), 5);
+        assert_eq!(ring.current(), Some(&4));
+        assert_eq!(values(&ring), vec![4, 0, 1, 2, 3]);
+
+        ring.clockwise(2);
+        assert_eq!(ring.current(), Some(&1));
+
+        ring.counter_clockwise(3);
+        assert_eq!(ring.current(), Some(&3));
+
+        ring.clockwise(1_000_000);
+        assert_eq!(ring.current(), Some(&3));
+    }
+
+    #[test]
+    fn inserting_and_removing_works() {
+        let mut ring = Ring::new();
+
+        assert!(ring.is_empty());
+        assert_eq!(ring.remove(), None);
+
+        ring.insert(0);
+        ring.insert(1);
+        ring.counter_clockwise(1);
+        ring.insert(2);
+        assert_eq!(values(&ring), vec![2, 1, 0]);
+
+        assert_eq!(ring.remove(), Some(2));
+        assert_eq!(values(&ring), vec![1, 0]);
+
+        // The freed node is reused
+        ring.insert(3);
+        assert_eq!(ring.nodes.len(), 3);
+        assert_eq!(values(&ring), vec![3, 0, 1]);
+
+        assert_eq!(ring.remove(), Some(3));
+        assert_eq!(ring.remove(), Some(0));
+        assert_eq!(ring.remove(), Some(1));
+        assert!(ring.is_empty());
+        assert_eq!(ring.current(), None);
+        assert_eq!(values(&ring), vec![]);
+
+        ring.insert(4);
+        assert_eq!(values(&ring), vec![4]);
+    }
+}